@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+/// The part of mdBook's `Book` the conversion needs, for builds without the
+/// `mdbook` feature.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Book {
+    sections: Vec<BookItem>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum BookItem {
+    Chapter(Chapter),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Chapter {
+    pub(crate) name: String,
+    pub(crate) content: String,
+    pub(crate) number: Option<Vec<u32>>,
+    pub(crate) sub_items: Vec<BookItem>,
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) source_path: Option<PathBuf>,
+}
+
+impl Book {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn push_item(&mut self, item: impl Into<BookItem>) -> &mut Self {
+        self.sections.push(item.into());
+        self
+    }
+
+    /// Visits every item depth first, like mdBook's.
+    pub(crate) fn for_each_mut(&mut self, mut f: impl FnMut(&mut BookItem)) {
+        fn visit(items: &mut [BookItem], f: &mut impl FnMut(&mut BookItem)) {
+            for item in items {
+                let BookItem::Chapter(chapter) = item;
+                visit(&mut chapter.sub_items, f);
+                f(item);
+            }
+        }
+        visit(&mut self.sections, &mut f);
+    }
+}
+
+impl Chapter {
+    pub(crate) fn new(
+        name: &str,
+        content: String,
+        path: impl Into<PathBuf>,
+        _parent_names: Vec<String>,
+    ) -> Self {
+        let path = path.into();
+        Self {
+            name: name.into(),
+            content,
+            number: None,
+            sub_items: vec![],
+            path: Some(path.clone()),
+            source_path: Some(path),
+        }
+    }
+}
+
+impl From<Chapter> for BookItem {
+    fn from(chapter: Chapter) -> Self {
+        Self::Chapter(chapter)
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{config::Config, location, metadata::TableInfo, Chapter};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt, fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// The `cache` directory, with one file for every chapter converted by an
+/// earlier build that `mdbook serve` can take as is. The files are named
+/// after the hash of the chapter's location and the hash of its content, so
+/// the old one can be found when the chapter changes.
+pub(crate) struct Cache {
+    dir: PathBuf,
+    /// Every option, so changing `book.toml` starts over.
+    config: String,
+}
+
+/// A chapter as it was before and after [`crate::RollTables::handle_chapter`].
+/// The whole input is kept to tell it apart from others with the same hash.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    version: String,
+    config: String,
+    location: String,
+    content: String,
+    converted: String,
+    tables: Vec<TableInfo>,
+}
+
+impl Cache {
+    pub(crate) fn new(dir: &Path, config: &Config) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            config: format!("{:?}", config),
+        }
+    }
+
+    /// The converted content and tables of `chapter` if they are cached.
+    /// Files that can't be read count as not cached, they are written again.
+    pub(crate) fn get(&self, chapter: &Chapter) -> Option<(String, Vec<TableInfo>)> {
+        let path = self.path(&location(chapter), &chapter.content);
+        let file = fs::read_to_string(path).ok()?;
+        let entry: Entry = serde_json::from_str(&file).ok()?;
+        if entry.version == env!("CARGO_PKG_VERSION")
+            && entry.config == self.config
+            && entry.location == location(chapter)
+            && entry.content == chapter.content
+        {
+            Some((entry.converted, entry.tables))
+        } else {
+            None
+        }
+    }
+
+    /// Caches the conversion of `content`, the content of `chapter` before
+    /// it was converted, and removes the entry of what it was before.
+    pub(crate) fn put(
+        &self,
+        content: String,
+        chapter: &Chapter,
+        tables: &[TableInfo],
+    ) -> Result<()> {
+        let entry = Entry {
+            version: env!("CARGO_PKG_VERSION").into(),
+            config: self.config.clone(),
+            location: location(chapter),
+            content,
+            converted: chapter.content.clone(),
+            tables: tables.to_vec(),
+        };
+        let path = self.path(&entry.location, &entry.content);
+        let file = serde_json::to_string(&entry)?;
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&path, file))
+            .with_context(|| format!("Couldn't write the cache {}", path.display()))?;
+
+        let prefix = format!("{:016x}-", hash(&entry.location));
+        let files = fs::read_dir(&self.dir)
+            .with_context(|| format!("Couldn't read the cache {}", self.dir.display()))?;
+        for old in files {
+            let old = old?.path();
+            let stale = old != path
+                && old
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(&prefix));
+            if stale {
+                fs::remove_file(&old)
+                    .with_context(|| format!("Couldn't remove the cache {}", old.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&self, location: &str, content: &str) -> PathBuf {
+        self.dir.join(format!(
+            "{:016x}-{:016x}.json",
+            hash(location),
+            hash(content)
+        ))
+    }
+}
+
+/// Called with the location of every chapter that is converted rather than
+/// taken from the cache, see
+/// [`RollTablesBuilder::on_convert`](crate::RollTablesBuilder::on_convert).
+#[derive(Clone, Default)]
+pub(crate) struct ConvertHook(Option<Arc<ConvertFn>>);
+
+type ConvertFn = dyn Fn(&str) + Send + Sync;
+
+impl ConvertHook {
+    pub(crate) fn new(f: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(f)))
+    }
+
+    pub(crate) fn call(&self, location: &str) {
+        if let Some(f) = &self.0 {
+            f(location);
+        }
+    }
+}
+
+impl fmt::Debug for ConvertHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.0.is_some() {
+            "ConvertHook(..)"
+        } else {
+            "ConvertHook(None)"
+        })
+    }
+}
+
+/// Two hooks are the same if they call the same function.
+impl PartialEq for ConvertHook {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+}
+
+impl Eq for ConvertHook {}
+
+fn hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Removes the `cache` directory `dir`, if there is one.
+#[cfg(feature = "mdbook")]
+pub(crate) fn clear_cache(dir: &Path) -> Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Couldn't remove the cache {}", dir.display()))
+        }
+        _ => Ok(()),
+    }
+}
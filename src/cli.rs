@@ -0,0 +1,273 @@
+use crate::{cache::clear_cache, RollTables};
+use anyhow::{anyhow, Context};
+use mdbook::{
+    config::Config,
+    errors::Result,
+    preprocess::{CmdPreprocessor, Preprocessor},
+    MDBook,
+};
+use semver::{Version, VersionReq};
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+use toml::{value::Table, Value};
+
+const HELP: &str = "\
+mdbook-rolltables is a preprocessor for mdBook, which runs it with the book as
+JSON on stdin. It can also be used directly with:
+
+    mdbook-rolltables supports <renderer>
+    mdbook-rolltables process --stdin [--option value]...
+    mdbook-rolltables check [--workspace] [--root path/to/book]... [--config key=value]...
+    mdbook-rolltables --clear-cache [--root path/to/book]
+";
+
+/// Runs the `mdbook-rolltables` binary with `args`, without the name of the
+/// binary, reading from `stdin` and writing to `stdout`, e.g. to bundle it in
+/// another binary. Errors and warnings still go to stderr. Returns the exit
+/// code: 0 on success, 1 on errors and problems found by `check`, and 2 if
+/// `stdin` isn't the book mdBook sends.
+pub fn run_cli<I>(args: I, stdin: impl Read, stdout: impl Write) -> i32
+where
+    I: IntoIterator,
+    I::Item: Into<OsString>,
+{
+    let args = args.into_iter().map(Into::into).collect();
+    match run(args, stdin, stdout) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            1
+        }
+    }
+}
+
+fn run(args: Vec<OsString>, mut stdin: impl Read, mut stdout: impl Write) -> Result<i32> {
+    let preprocessor = RollTables::default();
+    let mut args = pico_args::Arguments::from_vec(args);
+    if args.contains("-h") || args.contains("--help") {
+        write!(stdout, "{}", HELP)?;
+        return Ok(0);
+    }
+    if args.contains("--clear-cache") {
+        let root = match args.opt_value_from_str("--root")? {
+            Some(root) => root,
+            None => find_book(env::current_dir()?)?,
+        };
+        let config = Config::from_disk(root.join("book.toml"))
+            .with_context(|| format!("Couldn't load the book in {}", root.display()))?;
+        match preprocessor
+            .config(config.get_preprocessor(preprocessor.name()))?
+            .cache
+        {
+            Some(cache) => clear_cache(&root.join(cache))?,
+            None => Err(anyhow!(
+                "The book in {} has no cache set in [preprocessor.rolltables]",
+                root.display()
+            ))?,
+        }
+        return Ok(0);
+    }
+
+    match args.subcommand()?.as_deref() {
+        Some("supports") => {
+            let renderer: String = args.free_from_str()?;
+            Ok(if preprocessor.supports_renderer(&renderer) {
+                0
+            } else {
+                1
+            })
+        }
+        Some("process") => {
+            if !args.contains("--stdin") {
+                Err(anyhow!(
+                    "process needs --stdin, it only reads markdown from stdin"
+                ))?;
+            }
+            let cfg = options_from_flags(args.finish())?;
+
+            let mut markdown = String::new();
+            stdin.read_to_string(&mut markdown)?;
+            let converted = preprocessor.process_markdown(&markdown, Some(&cfg))?;
+            stdout.write_all(converted.as_bytes())?;
+
+            Ok(0)
+        }
+        Some("check") => {
+            let mut roots: Vec<PathBuf> = args.values_from_str("--root")?;
+            let workspace = args.contains("--workspace");
+            let overrides: Vec<String> = args.values_from_str("--config")?;
+            while let Some(root) = args.opt_free_from_str()? {
+                roots.push(root);
+            }
+            if workspace {
+                let dir = env::current_dir()?;
+                let books = find_books(&dir)?;
+                if books.is_empty() {
+                    Err(anyhow!("No book.toml in {} or below it", dir.display()))?;
+                }
+                roots.extend(books);
+            } else if roots.is_empty() {
+                roots.push(find_book(env::current_dir()?)?);
+            }
+
+            // With several books the problems are listed under each one.
+            let mut failed = false;
+            for root in &roots {
+                let problems = check(&preprocessor, root, &overrides)?;
+                let indent = if roots.len() > 1 { "  " } else { "" };
+                if roots.len() > 1 {
+                    writeln!(stdout, "{}:", root.display())?;
+                    if problems.is_empty() {
+                        writeln!(stdout, "  no problems")?;
+                    }
+                }
+                for problem in &problems {
+                    writeln!(stdout, "{}{}", indent, problem)?;
+                }
+                failed |= !problems.is_empty();
+            }
+            Ok(failed as i32)
+        }
+        Some(subcommand) => Err(anyhow!("Unknown subcommand \"{}\"", subcommand)),
+        None => preprocess(&preprocessor, stdin, stdout),
+    }
+}
+
+/// Runs the preprocessor the way mdBook does, with the book as JSON on
+/// `input` and the converted one written to `output`. Returns 2 if `input`
+/// isn't what mdBook sends.
+fn preprocess(preprocessor: &RollTables, input: impl Read, output: impl Write) -> Result<i32> {
+    let (ctx, book) = match CmdPreprocessor::parse_input(input) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!(
+                "Error: the input isn't a book from mdBook ({})\n\n{}",
+                e, HELP
+            );
+            return Ok(2);
+        }
+    };
+
+    let book_version = Version::parse(&ctx.mdbook_version)?;
+    let version_req = VersionReq::parse(mdbook::MDBOOK_VERSION)?;
+
+    if !version_req.matches(&book_version) {
+        eprintln!(
+            "Warning: The {} preprocessor was built against version {} of mdbook, \
+             but the preprocessor is being called from version {}",
+            preprocessor.name(),
+            mdbook::MDBOOK_VERSION,
+            ctx.mdbook_version
+        );
+    }
+
+    let processed_book = preprocessor.run(&ctx, book)?;
+    serde_json::to_writer(output, &processed_book)?;
+
+    Ok(0)
+}
+
+/// The problems `check` finds in the book in `root`, with its own
+/// `[preprocessor.rolltables]` and the `--config` options on top.
+fn check(preprocessor: &RollTables, root: &Path, overrides: &[String]) -> Result<Vec<String>> {
+    let book = MDBook::load(root)
+        .with_context(|| format!("Couldn't load the book in {}", root.display()))?;
+    let mut cfg = book
+        .config
+        .get_preprocessor(preprocessor.name())
+        .cloned()
+        .unwrap_or_default();
+    for option in overrides {
+        match option.split_once('=') {
+            Some((key, value)) => cfg.insert(key.trim().into(), parse_value(value.trim().into())),
+            None => Err(anyhow!("Expected --config key=value, got \"{}\"", option))?,
+        };
+    }
+    preprocessor.check_book(book.book, Some(&cfg))
+}
+
+/// Every book under `dir` for `check --workspace`, i.e. the directories with
+/// a `book.toml`. Hidden directories and `target` are skipped.
+fn find_books(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut books = vec![];
+    if dir.join("book.toml").is_file() {
+        books.push(dir.to_path_buf());
+    }
+
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("Couldn't read {}", dir.display()))?
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if entry.file_type()?.is_dir() && !name.starts_with('.') && name != "target" {
+            books.extend(find_books(&entry.path())?);
+        }
+    }
+
+    Ok(books)
+}
+
+/// The book the current directory is in, like mdBook finds it: the closest
+/// directory upwards with a `book.toml`.
+fn find_book(start: PathBuf) -> Result<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| dir.join("book.toml").is_file())
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            anyhow!(
+                "No book.toml in {} or any directory above it, use --root to point to the book",
+                start.display()
+            )
+        })
+}
+
+/// Reads an option value as TOML if it can be, e.g. `2` or `["html"]`, and
+/// as a string otherwise.
+fn parse_value(value: String) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or(Value::String(value))
+}
+
+/// Turns `--separator / --strict` into the `[preprocessor.rolltables]` table
+/// `separator = "/"` and `strict = true`. A flag without a value is `true`,
+/// values are read as TOML if they can be, e.g. `--face-wrap-size 2`, and as
+/// strings otherwise.
+fn options_from_flags(flags: Vec<OsString>) -> Result<Table> {
+    let mut cfg = Table::new();
+    let mut flags = flags
+        .into_iter()
+        .map(|flag| {
+            flag.into_string()
+                .map_err(|flag| anyhow!("Invalid argument {:?}", flag))
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .peekable();
+
+    while let Some(flag) = flags.next() {
+        let key = match flag.strip_prefix("--") {
+            Some(key) => key.to_string(),
+            None => Err(anyhow!(
+                "Expected an option like --separator, got \"{}\"",
+                flag
+            ))?,
+        };
+        let value = match flags.next_if(|value| !value.starts_with("--")) {
+            Some(value) => parse_value(value),
+            None => Value::Boolean(true),
+        };
+        cfg.insert(key, value);
+    }
+
+    Ok(cfg)
+}
@@ -0,0 +1,799 @@
+use crate::{
+    cache::ConvertHook,
+    diagnostics::{Report, Sink},
+    dice::doubled_die,
+    header::ExplicitDie,
+    DiceOptions, RollTablesBuilder,
+};
+use anyhow::{anyhow, Result};
+use pulldown_cmark::Alignment;
+use std::{collections::BTreeMap, env};
+use toml::{value::Table, Value};
+
+/// Options resolved from the environment, the [`RollTablesBuilder`] and
+/// `book.toml`, in that order of precedence, made with
+/// [`RollTables::config`](crate::RollTables::config).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub(crate) head_separator: String,
+    pub(crate) separator: String,
+    pub(crate) warn_unusual_dice: UnusualDice,
+    pub(crate) face_wrap: FaceWrap,
+    pub(crate) face_wrap_size: usize,
+    pub(crate) face_style: FaceStyle,
+    pub(crate) center_die_column: CenterDieColumn,
+    pub(crate) generated_column_alignment: ColumnAlignment,
+    pub(crate) strict: bool,
+    pub(crate) range_separator: Option<String>,
+    pub(crate) quiet: bool,
+    /// Also prints how the die of a table was picked when another die
+    /// would have fit, e.g. a pair.
+    pub(crate) verbose: bool,
+    pub(crate) pretty_tables: bool,
+    pub(crate) stats: bool,
+    /// Set by `stats = "detailed"`, adds the chances of the rows.
+    pub(crate) detailed_stats: bool,
+    pub(crate) warn_duplicates: bool,
+    pub(crate) duplicates_ignore_case: bool,
+    pub(crate) duplicates_min_rows: usize,
+    /// Dice with named faces from `[preprocessor.rolltables.custom-dice]`.
+    pub(crate) custom_dice: BTreeMap<String, Vec<String>>,
+    /// Ranges of 2d6 sums by preset name, for `2d6!name` headers and
+    /// `bands=name` directives. `reaction` is built in.
+    pub(crate) bands: BTreeMap<String, Vec<(u64, u64)>>,
+    /// The header markers of tables that get faces, e.g. "d", and how
+    /// their die is chosen.
+    pub(crate) markers: BTreeMap<String, MarkerPolicy>,
+    pub(crate) custom_face_label: String,
+    pub(crate) matrix_corner: String,
+    pub(crate) manifest: Option<String>,
+    /// The `cache` directory, relative to the book root.
+    pub(crate) cache: Option<String>,
+    pub(crate) d66_dice: D66Dice,
+    pub(crate) d66_d10_suffix: String,
+    /// Renderers the book is converted for, all of them if `None`.
+    pub(crate) convert_in: Option<Vec<String>>,
+    pub(crate) sort_ignore_case: bool,
+    pub(crate) merge_duplicates: MergeDuplicates,
+    pub(crate) style_dice_in_prose: ProseDice,
+    pub(crate) html_table_support: bool,
+    /// Die rolled for every table whose rows split its faces evenly.
+    pub(crate) default_die: Option<usize>,
+    pub(crate) assume_empty_header: bool,
+    pub(crate) empty_header_min_rows: usize,
+    /// Numbers of rows rolled with a die with a multiple of their faces, a
+    /// group of faces per row.
+    pub(crate) double_small_tables: Vec<usize>,
+    pub(crate) die_markup: DieMarkup,
+    pub(crate) preserve_line_endings: bool,
+    /// Splits the entries of `rolltable` blocks into columns.
+    pub(crate) rolltable_delimiter: String,
+    /// Title of the chapter with the tables tagged with `screen`.
+    pub(crate) screen_chapter: Option<String>,
+    pub(crate) single_column_output: bool,
+    /// Markdown written in front of the results by `single-column-output`,
+    /// with `{faces}` replaced by the faces of the row.
+    pub(crate) single_column_label: String,
+    pub(crate) emit_metadata: bool,
+    /// Appends the content of chapters with converted tables as it was
+    /// before.
+    pub(crate) debug_keep_original: KeepOriginal,
+    pub(crate) caption_detection: bool,
+    /// Pairs of start and end comments, like the ones of a tabs plugin,
+    /// whose tables are left as they are.
+    pub(crate) protected_regions: Vec<(String, String)>,
+    pub(crate) convert_in_protected: bool,
+    pub(crate) report: Report,
+    pub(crate) sink: Sink,
+    pub(crate) on_convert: ConvertHook,
+    /// Undocumented `assert-stable` for CI, checks that converting the
+    /// output again doesn't change it.
+    pub(crate) assert_stable: bool,
+    /// Set by `MDBOOK_ROLLTABLES_DISABLE`, leaves the book untouched.
+    pub(crate) disabled: bool,
+}
+
+/// How the faces of a row with several of them are laid out (`face-wrap`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaceWrap {
+    /// All faces on one line, e.g. "1, 2, 3, 4".
+    None,
+    /// A `<br>` after every `face-wrap-size` faces.
+    Br,
+}
+
+/// How the generated faces are written (`face-style`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaceStyle {
+    /// As text, e.g. 1, 2.
+    Plain,
+    /// In a code span, e.g. `1, 2`, to get a monospace font.
+    Code,
+}
+
+/// Whether rows with the same result are merged (`merge-duplicates`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MergeDuplicates {
+    /// Every row keeps its own faces.
+    Never,
+    /// Only rows right after each other.
+    Adjacent,
+    /// Every row with the same result as an earlier one.
+    All,
+}
+
+/// How dice expressions like 2d6+1 in the text around the tables are
+/// formatted (`style-dice-in-prose`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProseDice {
+    /// Left as written.
+    Unchanged,
+    /// In bold, e.g. **2d6**.
+    Bold,
+    /// In a code span, e.g. `2d6`.
+    Code,
+}
+
+/// How the dice in headers and prose are written (`die-markup`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DieMarkup {
+    /// As text, e.g. d66.
+    Plain,
+    /// In `<span class="die">`, so CSS can style them. Only in HTML.
+    Span,
+    /// With the faces as superscript, e.g. d⁶⁶.
+    Superscript,
+}
+
+/// How the die of a table with a marker from `markers` is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MarkerPolicy {
+    /// The usual die for the number of rows, like for "d".
+    Auto,
+    /// A d100 if the rows split its faces evenly.
+    Percentile,
+    /// Two standard dice read one after the other, e.g. d10d10 for 100 rows,
+    /// if the number of rows is their product.
+    Combination,
+}
+
+/// Which dice a 36 row d66 table is rolled with (`d66-dice`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum D66Dice {
+    /// Two d6.
+    D6,
+    /// Two d10, rerolling everything above 6, noted in the header with
+    /// `d66-d10-suffix`.
+    D10,
+}
+
+/// Which unusual dice like d7 are warned about (`warn-unusual-dice`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnusualDice {
+    /// None of them (`false`).
+    Never,
+    /// The ones picked for the number of rows, not the ones written in a
+    /// header like `d7` (`true`).
+    Picked,
+    /// Both (`"always"`).
+    Always,
+}
+
+/// For which renderers chapters with converted tables end with a comment
+/// with the chapter as it was before (`debug-keep-original`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeepOriginal {
+    /// None of them (`false`).
+    Never,
+    /// Only the html renderer (`true`).
+    Html,
+    /// Every renderer (`"always"`).
+    Always,
+}
+
+/// Whether converted tables get a centered die column (`center-die-column`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CenterDieColumn {
+    /// Keep the alignment from the source (`false`).
+    Never,
+    /// Always center the die column (`true`).
+    Always,
+    /// Center the die column unless it has an explicit alignment
+    /// (`"only-unset"`).
+    OnlyUnset,
+}
+
+/// How the columns added to tables, like the die column of
+/// `add-die-column`, are aligned (`generated-column-alignment`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColumnAlignment {
+    /// No alignment, `---` (`"none"`).
+    None,
+    /// `:---` (`"left"`).
+    Left,
+    /// `:---:` (`"center"`).
+    Center,
+    /// `---:` (`"right"`).
+    Right,
+}
+
+impl From<ColumnAlignment> for Alignment {
+    fn from(alignment: ColumnAlignment) -> Self {
+        match alignment {
+            ColumnAlignment::None => Alignment::None,
+            ColumnAlignment::Left => Alignment::Left,
+            ColumnAlignment::Center => Alignment::Center,
+            ColumnAlignment::Right => Alignment::Right,
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn new(cfg: Option<&Table>, overrides: &RollTablesBuilder) -> Result<Self> {
+        let empty = Table::new();
+        let cfg = cfg.unwrap_or(&empty);
+        let (stats, detailed_stats) = match cfg.get("stats") {
+            Some(Value::Boolean(b)) => (*b, false),
+            Some(Value::String(s)) if s == "detailed" => (true, true),
+            None => (false, false),
+            Some(_) => Err(anyhow!("stats must be a bool or \"detailed\""))?,
+        };
+        let detailed_stats = overrides.detailed_stats.unwrap_or(detailed_stats);
+
+        Ok(Self {
+            head_separator: check_separator(
+                "head-separator",
+                match &overrides.head_separator {
+                    Some(s) => s.clone(),
+                    None => get_string(cfg, "head-separator")?.unwrap_or_default(),
+                },
+            )?,
+            separator: check_separator(
+                "separator",
+                match &overrides.separator {
+                    Some(s) => s.clone(),
+                    None => get_string(cfg, "separator")?.unwrap_or_else(|| ".".into()),
+                },
+            )?,
+            warn_unusual_dice: match overrides.warn_unusual_dice {
+                Some(w) => w,
+                None => match cfg.get("warn-unusual-dice") {
+                    Some(Value::Boolean(true)) => UnusualDice::Picked,
+                    Some(Value::Boolean(false)) | None => UnusualDice::Never,
+                    Some(Value::String(s)) if s == "always" => UnusualDice::Always,
+                    Some(_) => Err(anyhow!("warn-unusual-dice must be a bool or \"always\""))?,
+                },
+            },
+            face_wrap: match overrides.face_wrap {
+                Some(w) => w,
+                None => match get_string(cfg, "face-wrap")?.as_deref() {
+                    Some("none") | None => FaceWrap::None,
+                    Some("br") => FaceWrap::Br,
+                    Some(_) => Err(anyhow!("face-wrap must be \"none\" or \"br\""))?,
+                },
+            },
+            face_wrap_size: match overrides.face_wrap_size {
+                Some(n) => n,
+                None => get_usize(cfg, "face-wrap-size")?.unwrap_or(3),
+            },
+            face_style: match overrides.face_style {
+                Some(s) => s,
+                None => match get_string(cfg, "face-style")?.as_deref() {
+                    Some("plain") | None => FaceStyle::Plain,
+                    Some("code") => FaceStyle::Code,
+                    Some(_) => Err(anyhow!("face-style must be \"plain\" or \"code\""))?,
+                },
+            },
+            center_die_column: match overrides.center_die_column {
+                Some(c) => c,
+                None => match cfg.get("center-die-column") {
+                    Some(Value::Boolean(true)) => CenterDieColumn::Always,
+                    Some(Value::Boolean(false)) | None => CenterDieColumn::Never,
+                    Some(Value::String(s)) if s == "only-unset" => CenterDieColumn::OnlyUnset,
+                    Some(_) => Err(anyhow!(
+                        "center-die-column must be a bool or \"only-unset\""
+                    ))?,
+                },
+            },
+            generated_column_alignment: match overrides.generated_column_alignment {
+                Some(a) => a,
+                None => match get_string(cfg, "generated-column-alignment")?.as_deref() {
+                    Some("none") | None => ColumnAlignment::None,
+                    Some("left") => ColumnAlignment::Left,
+                    Some("center") => ColumnAlignment::Center,
+                    Some("right") => ColumnAlignment::Right,
+                    Some(_) => Err(anyhow!(
+                        "generated-column-alignment must be \"none\", \"left\", \"center\" \
+                         or \"right\""
+                    ))?,
+                },
+            },
+            strict: match get_env_bool("MDBOOK_ROLLTABLES_STRICT")?.or(overrides.strict) {
+                Some(b) => b,
+                None => get_bool(cfg, "strict")?.unwrap_or(false),
+            },
+            range_separator: match &overrides.range_separator {
+                Some(s) => Some(s.clone()),
+                None => get_string(cfg, "range-separator")?,
+            }
+            .map(|s| check_separator("range-separator", s))
+            .transpose()?,
+            quiet: match get_env_bool("MDBOOK_ROLLTABLES_QUIET")?.or(overrides.quiet) {
+                Some(b) => b,
+                None => get_bool(cfg, "quiet")?.unwrap_or(false),
+            },
+            verbose: match overrides.verbose {
+                Some(b) => b,
+                None => get_bool(cfg, "verbose")?.unwrap_or(false),
+            },
+            pretty_tables: match overrides.pretty_tables {
+                Some(b) => b,
+                None => get_bool(cfg, "pretty-tables")?.unwrap_or(false),
+            },
+            stats: overrides.stats.unwrap_or(stats) || detailed_stats,
+            detailed_stats,
+            warn_duplicates: match overrides.warn_duplicates {
+                Some(b) => b,
+                None => get_bool(cfg, "warn-duplicates")?.unwrap_or(false),
+            },
+            duplicates_ignore_case: match overrides.duplicates_ignore_case {
+                Some(b) => b,
+                None => get_bool(cfg, "duplicates-ignore-case")?.unwrap_or(false),
+            },
+            duplicates_min_rows: match overrides.duplicates_min_rows {
+                Some(n) => n,
+                None => get_usize(cfg, "duplicates-min-rows")?.unwrap_or(6),
+            },
+            custom_dice: {
+                let mut dice = get_custom_dice(cfg)?;
+                dice.extend(overrides.custom_dice.clone());
+                dice
+            },
+            markers: {
+                let mut markers = BTreeMap::new();
+                markers.insert("d".to_string(), MarkerPolicy::Auto);
+                markers.extend(get_markers(cfg)?);
+                markers.extend(overrides.markers.clone());
+                if let Some(marker) = markers.keys().find(|marker| {
+                    marker.is_empty()
+                        || marker.as_str() == "dxd"
+                        || marker.starts_with("d:")
+                        || marker.contains('|')
+                        || ExplicitDie::parse(marker).is_some()
+                }) {
+                    Err(anyhow!(
+                        "markers can't have \"{}\", it's empty or already means a die",
+                        marker
+                    ))?;
+                }
+                markers
+            },
+            bands: {
+                let mut presets = BTreeMap::new();
+                presets.insert(
+                    "reaction".to_string(),
+                    vec![(2, 2), (3, 5), (6, 8), (9, 11), (12, 12)],
+                );
+                for (name, bands) in get_bands(cfg)?.into_iter().chain(overrides.bands.clone()) {
+                    presets.insert(name.clone(), parse_bands(&name, &bands)?);
+                }
+                presets
+            },
+            custom_face_label: match &overrides.custom_face_label {
+                Some(s) => s.clone(),
+                None => get_string(cfg, "custom-face-label")?
+                    .unwrap_or_else(|| "{face} (×{count})".into()),
+            },
+            matrix_corner: match &overrides.matrix_corner {
+                Some(s) => s.clone(),
+                None => {
+                    get_string(cfg, "matrix-corner")?.unwrap_or_else(|| "{row} \\ {column}".into())
+                }
+            },
+            manifest: match &overrides.manifest {
+                Some(s) => Some(s.clone()),
+                None => get_string(cfg, "manifest")?,
+            },
+            cache: match &overrides.cache {
+                Some(s) => Some(s.clone()),
+                None => get_string(cfg, "cache")?,
+            },
+            d66_dice: match overrides.d66_dice {
+                Some(d) => d,
+                None => match get_string(cfg, "d66-dice")?.as_deref() {
+                    Some("d6") | None => D66Dice::D6,
+                    Some("d10") => D66Dice::D10,
+                    Some(_) => Err(anyhow!("d66-dice must be \"d6\" or \"d10\""))?,
+                },
+            },
+            d66_d10_suffix: match &overrides.d66_d10_suffix {
+                Some(s) => s.clone(),
+                None => get_string(cfg, "d66-d10-suffix")?
+                    .unwrap_or_else(|| " (reroll 0 and 7–9)".into()),
+            },
+            convert_in: match &overrides.convert_in {
+                Some(renderers) => Some(renderers.clone()),
+                None => get_string_array(cfg, "convert-in")?,
+            },
+            sort_ignore_case: match overrides.sort_ignore_case {
+                Some(b) => b,
+                None => get_bool(cfg, "sort-ignore-case")?.unwrap_or(false),
+            },
+            merge_duplicates: match overrides.merge_duplicates {
+                Some(m) => m,
+                None => match cfg.get("merge-duplicates") {
+                    Some(Value::Boolean(true)) => MergeDuplicates::Adjacent,
+                    Some(Value::Boolean(false)) | None => MergeDuplicates::Never,
+                    Some(Value::String(s)) if s == "all" => MergeDuplicates::All,
+                    Some(_) => Err(anyhow!("merge-duplicates must be a bool or \"all\""))?,
+                },
+            },
+            style_dice_in_prose: match overrides.style_dice_in_prose {
+                Some(s) => s,
+                None => match cfg.get("style-dice-in-prose") {
+                    Some(Value::Boolean(false)) | None => ProseDice::Unchanged,
+                    Some(Value::String(s)) if s == "bold" => ProseDice::Bold,
+                    Some(Value::String(s)) if s == "code" => ProseDice::Code,
+                    Some(_) => Err(anyhow!(
+                        "style-dice-in-prose must be \"bold\", \"code\" or false"
+                    ))?,
+                },
+            },
+            html_table_support: match overrides.html_table_support {
+                Some(b) => b,
+                None => get_bool(cfg, "html-table-support")?.unwrap_or(false),
+            },
+            default_die: match overrides.default_die {
+                Some(n) => Some(n),
+                None => get_usize(cfg, "default-die")?,
+            },
+            assume_empty_header: match overrides.assume_empty_header {
+                Some(b) => b,
+                None => get_bool(cfg, "assume-empty-header")?.unwrap_or(false),
+            },
+            empty_header_min_rows: match overrides.empty_header_min_rows {
+                Some(n) => n,
+                None => get_usize(cfg, "empty-header-min-rows")?.unwrap_or(4),
+            },
+            double_small_tables: {
+                let counts = match &overrides.double_small_tables {
+                    Some(counts) => counts.clone(),
+                    None => get_usize_array(cfg, "double-small-tables")?.unwrap_or_else(|| vec![3]),
+                };
+                if let Some(count) = counts.iter().find(|&&count| doubled_die(count).is_none()) {
+                    Err(anyhow!(
+                        "double-small-tables can't have {}, no standard die has a multiple \
+                         of {} faces",
+                        count,
+                        count
+                    ))?;
+                }
+                counts
+            },
+            die_markup: match overrides.die_markup {
+                Some(m) => m,
+                None => match get_string(cfg, "die-markup")?.as_deref() {
+                    Some("plain") | None => DieMarkup::Plain,
+                    Some("span") => DieMarkup::Span,
+                    Some("superscript") => DieMarkup::Superscript,
+                    Some(_) => Err(anyhow!(
+                        "die-markup must be \"plain\", \"span\" or \"superscript\""
+                    ))?,
+                },
+            },
+            preserve_line_endings: match overrides.preserve_line_endings {
+                Some(b) => b,
+                None => get_bool(cfg, "preserve-line-endings")?.unwrap_or(false),
+            },
+            rolltable_delimiter: {
+                let delimiter = match &overrides.rolltable_delimiter {
+                    Some(s) => s.clone(),
+                    None => get_string(cfg, "rolltable-delimiter")?.unwrap_or_else(|| "|".into()),
+                };
+                if delimiter.is_empty() {
+                    Err(anyhow!("rolltable-delimiter can't be empty"))?;
+                }
+                delimiter
+            },
+            screen_chapter: match &overrides.screen_chapter {
+                Some(s) => Some(s.clone()),
+                None => get_string(cfg, "screen-chapter")?,
+            },
+            single_column_output: match overrides.single_column_output {
+                Some(b) => b,
+                None => get_bool(cfg, "single-column-output")?.unwrap_or(false),
+            },
+            single_column_label: match &overrides.single_column_label {
+                Some(s) => s.clone(),
+                None => {
+                    get_string(cfg, "single-column-label")?.unwrap_or_else(|| "**{faces}**".into())
+                }
+            },
+            emit_metadata: match overrides.emit_metadata {
+                Some(b) => b,
+                None => get_bool(cfg, "emit-metadata")?.unwrap_or(false),
+            },
+            debug_keep_original: match overrides.debug_keep_original {
+                Some(k) => k,
+                None => match cfg.get("debug-keep-original") {
+                    Some(Value::Boolean(true)) => KeepOriginal::Html,
+                    Some(Value::Boolean(false)) | None => KeepOriginal::Never,
+                    Some(Value::String(s)) if s == "always" => KeepOriginal::Always,
+                    Some(_) => Err(anyhow!("debug-keep-original must be a bool or \"always\""))?,
+                },
+            },
+            caption_detection: match overrides.caption_detection {
+                Some(b) => b,
+                None => get_bool(cfg, "caption-detection")?.unwrap_or(false),
+            },
+            protected_regions: match &overrides.protected_regions {
+                Some(regions) => regions.clone(),
+                None => get_protected_regions(cfg)?,
+            },
+            convert_in_protected: match overrides.convert_in_protected {
+                Some(b) => b,
+                None => get_bool(cfg, "convert-in-protected")?.unwrap_or(false),
+            },
+            report: overrides.report.clone(),
+            sink: overrides.sink.clone(),
+            on_convert: overrides.on_convert.clone(),
+            assert_stable: get_bool(cfg, "assert-stable")?.unwrap_or(false),
+            disabled: get_env_bool("MDBOOK_ROLLTABLES_DISABLE")?.unwrap_or(false),
+        })
+    }
+
+    /// The options [`select_die`](crate::select_die) gets for a table with
+    /// `marker`.
+    pub fn dice_options(&self, marker: MarkerPolicy) -> DiceOptions {
+        DiceOptions {
+            marker,
+            default_die: self.default_die,
+            double_small_tables: self.double_small_tables.clone(),
+        }
+    }
+
+    /// Whether the book is converted for `renderer` (`convert-in`).
+    #[cfg(feature = "mdbook")]
+    pub(crate) fn converts_in(&self, renderer: &str) -> bool {
+        match &self.convert_in {
+            Some(renderers) => renderers.iter().any(|r| r == renderer),
+            None => true,
+        }
+    }
+
+    /// Turns off the options that only work in HTML, so e.g. the `markdown`
+    /// renderer doesn't get `<br>` in its output.
+    #[cfg(feature = "mdbook")]
+    pub(crate) fn restrict_to(&mut self, renderer: &str) {
+        if renderer != "html" && self.face_wrap == FaceWrap::Br {
+            log::debug!("Ignoring face-wrap = \"br\" for the {} renderer", renderer);
+            self.face_wrap = FaceWrap::None;
+        }
+        if renderer != "html" && self.die_markup == DieMarkup::Span {
+            log::debug!(
+                "Ignoring die-markup = \"span\" for the {} renderer",
+                renderer
+            );
+            self.die_markup = DieMarkup::Plain;
+        }
+        if renderer != "html" && self.debug_keep_original == KeepOriginal::Html {
+            log::debug!("Ignoring debug-keep-original for the {} renderer", renderer);
+            self.debug_keep_original = KeepOriginal::Never;
+        }
+    }
+}
+
+/// Fails for `|` and line breaks, which would end the cell or the row of the
+/// table the separator is written in. Other markdown like `*` is escaped
+/// when the table is written.
+fn check_separator(key: &str, separator: String) -> Result<String> {
+    if separator.contains(['|', '\n', '\r'].as_ref()) {
+        Err(anyhow!(
+            "{} can't contain \"|\" or a line break, they would break the table",
+            key
+        ))?;
+    }
+    Ok(separator)
+}
+
+fn get_string(cfg: &Table, key: &str) -> Result<Option<String>> {
+    match cfg.get(key) {
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(anyhow!("{} must be a string", key)),
+        None => Ok(None),
+    }
+}
+
+fn get_bool(cfg: &Table, key: &str) -> Result<Option<bool>> {
+    match cfg.get(key) {
+        Some(Value::Boolean(b)) => Ok(Some(*b)),
+        Some(_) => Err(anyhow!("{} must be a bool", key)),
+        None => Ok(None),
+    }
+}
+
+fn get_usize(cfg: &Table, key: &str) -> Result<Option<usize>> {
+    match cfg.get(key) {
+        Some(Value::Integer(i)) if *i > 0 => Ok(Some(*i as usize)),
+        Some(_) => Err(anyhow!("{} must be a positive integer", key)),
+        None => Ok(None),
+    }
+}
+
+fn get_string_array(cfg: &Table, key: &str) -> Result<Option<Vec<String>>> {
+    match cfg.get(key) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| value.as_str().map(String::from))
+            .collect::<Option<Vec<_>>>()
+            .map(Some)
+            .ok_or_else(|| anyhow!("{} must be an array of strings", key)),
+        Some(_) => Err(anyhow!("{} must be an array of strings", key)),
+        None => Ok(None),
+    }
+}
+
+fn get_usize_array(cfg: &Table, key: &str) -> Result<Option<Vec<usize>>> {
+    match cfg.get(key) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| match value {
+                Value::Integer(i) if *i > 0 => Some(*i as usize),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(Some)
+            .ok_or_else(|| anyhow!("{} must be an array of positive integers", key)),
+        Some(_) => Err(anyhow!("{} must be an array of positive integers", key)),
+        None => Ok(None),
+    }
+}
+
+fn get_custom_dice(cfg: &Table) -> Result<BTreeMap<String, Vec<String>>> {
+    let dice = match cfg.get("custom-dice") {
+        Some(Value::Table(dice)) => dice,
+        Some(_) => Err(anyhow!("custom-dice must be a table"))?,
+        None => return Ok(BTreeMap::new()),
+    };
+
+    dice.iter()
+        .map(|(name, faces)| {
+            let faces = match faces {
+                Value::Array(faces) if !faces.is_empty() => faces
+                    .iter()
+                    .map(|face| face.as_str().map(String::from))
+                    .collect::<Option<Vec<_>>>(),
+                _ => None,
+            };
+            match faces {
+                Some(faces) => Ok((name.clone(), faces)),
+                None => Err(anyhow!(
+                    "custom-dice.{} must be a non-empty array of strings",
+                    name
+                )),
+            }
+        })
+        .collect()
+}
+
+fn get_markers(cfg: &Table) -> Result<BTreeMap<String, MarkerPolicy>> {
+    let markers = match cfg.get("markers") {
+        Some(Value::Table(markers)) => markers,
+        Some(_) => Err(anyhow!("markers must be a table"))?,
+        None => return Ok(BTreeMap::new()),
+    };
+
+    markers
+        .iter()
+        .map(|(marker, policy)| {
+            let policy = match policy.as_str() {
+                Some("auto") => MarkerPolicy::Auto,
+                Some("percentile") => MarkerPolicy::Percentile,
+                Some("combination") => MarkerPolicy::Combination,
+                _ => Err(anyhow!(
+                    "markers.{} must be \"auto\", \"percentile\" or \"combination\"",
+                    marker
+                ))?,
+            };
+            Ok((marker.clone(), policy))
+        })
+        .collect()
+}
+
+fn get_bands(cfg: &Table) -> Result<BTreeMap<String, Vec<String>>> {
+    let presets = match cfg.get("bands") {
+        Some(Value::Table(presets)) => presets,
+        Some(_) => Err(anyhow!("bands must be a table"))?,
+        None => return Ok(BTreeMap::new()),
+    };
+
+    presets
+        .iter()
+        .map(|(name, bands)| {
+            let bands = match bands {
+                Value::Array(bands) => bands
+                    .iter()
+                    .map(|band| band.as_str().map(String::from))
+                    .collect::<Option<Vec<_>>>(),
+                _ => None,
+            };
+            match bands {
+                Some(bands) => Ok((name.clone(), bands)),
+                None => Err(anyhow!("bands.{} must be an array of strings", name)),
+            }
+        })
+        .collect()
+}
+
+fn get_protected_regions(cfg: &Table) -> Result<Vec<(String, String)>> {
+    let regions = match cfg.get("protected-regions") {
+        Some(Value::Array(regions)) => regions,
+        Some(_) => Err(anyhow!("protected-regions must be an array"))?,
+        None => return Ok(vec![]),
+    };
+
+    regions
+        .iter()
+        .map(|region| {
+            let markers = region.as_array().map(|markers| {
+                markers
+                    .iter()
+                    .map(|marker| marker.as_str().map(str::trim))
+                    .collect::<Vec<_>>()
+            });
+            match markers.as_deref() {
+                Some([Some(start), Some(end)]) if !start.is_empty() && !end.is_empty() => {
+                    Ok((start.to_string(), end.to_string()))
+                }
+                _ => Err(anyhow!(
+                    "protected-regions must be pairs of start and end comments like \
+                     [\"<!-- tabs:start -->\", \"<!-- tabs:end -->\"]"
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Reads bands like `"2-3"` or `"12"`, which have to cover the sums of 2d6
+/// from 2 to 12 in order without gaps.
+fn parse_bands(name: &str, bands: &[String]) -> Result<Vec<(u64, u64)>> {
+    let mut parsed = vec![];
+    let mut next = 2;
+    for band in bands {
+        let (first, last) = match band.split_once(['-', '–'].as_ref()) {
+            Some((first, last)) => (first.trim().parse(), last.trim().parse()),
+            None => (band.trim().parse(), band.trim().parse()),
+        };
+        match (first, last) {
+            (Ok(first), Ok(last)) if first == next && last >= first && last <= 12 => {
+                parsed.push((first, last));
+                next = last + 1;
+            }
+            _ => Err(anyhow!(
+                "bands.{} has \"{}\", the bands must be ranges like \"3-5\" covering 2 to 12 \
+                 in order",
+                name,
+                band
+            ))?,
+        }
+    }
+    if next != 13 {
+        Err(anyhow!(
+            "bands.{} doesn't cover the sums of 2d6 up to 12",
+            name
+        ))?;
+    }
+    Ok(parsed)
+}
+
+/// Accepts `1`/`0` and `true`/`false`, an empty variable counts as unset.
+fn get_env_bool(key: &str) -> Result<Option<bool>> {
+    match env::var(key).as_deref() {
+        Ok("1") | Ok("true") => Ok(Some(true)),
+        Ok("0") | Ok("false") => Ok(Some(false)),
+        Ok("") | Err(env::VarError::NotPresent) => Ok(None),
+        Ok(_) | Err(env::VarError::NotUnicode(_)) => {
+            Err(anyhow!("{} must be 1, 0, true or false", key))
+        }
+    }
+}
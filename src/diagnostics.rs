@@ -0,0 +1,586 @@
+use crate::{config::Config, display_path, Chapter};
+use anyhow::{anyhow, Result};
+use std::{
+    fmt,
+    io::{self, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// A problem found while converting a book. Where it was found is kept
+/// separately, so the same problem in many tables is reported once.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Warning {
+    /// The events ended before `End(Table)`, e.g. in a truncated
+    /// `{{#include}}` fragment.
+    Truncated,
+    NoDieForOverflow {
+        rows: usize,
+    },
+    NoFacesForOverflow,
+    LabelCount {
+        labels: usize,
+        rows: usize,
+    },
+    EmptyLabel,
+    Duplicate {
+        text: String,
+        faces: Vec<String>,
+    },
+    AmbiguousSeparator {
+        die: String,
+    },
+    AmbiguousHeadSeparator {
+        die: String,
+    },
+    UnusualDie {
+        die: String,
+        /// Which standard dice are a few rows away, if the die is one face
+        /// per row.
+        suggestion: Option<String>,
+    },
+    InvalidDie {
+        die: String,
+        suggestion: String,
+        rows: usize,
+    },
+    TooFewFaces {
+        die: String,
+        rows: usize,
+    },
+    TooManyFaces {
+        die: String,
+    },
+    UnevenFaces {
+        die: String,
+        rows: usize,
+    },
+    UnknownCustomDie {
+        name: String,
+    },
+    CustomDieRows {
+        name: String,
+        faces: usize,
+        rows: usize,
+    },
+    /// A header like `d66` on a table with another number of rows.
+    PairRows {
+        die: String,
+        rows: usize,
+    },
+    MalformedHtmlTable {
+        reason: &'static str,
+    },
+    UnevenDefaultDie {
+        die: usize,
+        rows: usize,
+        /// [`DieChoice::describe`](crate::DieChoice::describe) of the die
+        /// used instead.
+        instead: String,
+    },
+    /// The head and delimiter rows of what looks like a roll table have a
+    /// different number of cells, so it isn't a table at all.
+    MismatchedColumns {
+        head: usize,
+        delimiters: usize,
+    },
+    /// A start comment of `protected-regions` without its end, so the rest
+    /// of the chapter is protected.
+    UnclosedProtectedRegion {
+        start: String,
+    },
+    UnmatchedProtectedEnd {
+        end: String,
+    },
+    /// What looks like a roll table is in an HTML block, e.g. one another
+    /// preprocessor wrapped the chapter's markdown in, in a chapter without
+    /// any markdown table.
+    TableInHtml,
+}
+
+impl Warning {
+    fn kind(&self) -> WarningKind {
+        match self {
+            Warning::Truncated => WarningKind::Truncated,
+            Warning::NoDieForOverflow { .. } => WarningKind::NoDieForOverflow,
+            Warning::NoFacesForOverflow => WarningKind::NoFacesForOverflow,
+            Warning::LabelCount { .. } => WarningKind::LabelCount,
+            Warning::EmptyLabel => WarningKind::EmptyLabel,
+            Warning::Duplicate { .. } => WarningKind::Duplicate,
+            Warning::AmbiguousSeparator { .. } => WarningKind::AmbiguousSeparator,
+            Warning::AmbiguousHeadSeparator { .. } => WarningKind::AmbiguousHeadSeparator,
+            Warning::UnusualDie { .. } => WarningKind::UnusualDie,
+            Warning::InvalidDie { .. } => WarningKind::InvalidDie,
+            Warning::TooFewFaces { .. } => WarningKind::TooFewFaces,
+            Warning::TooManyFaces { .. } => WarningKind::TooManyFaces,
+            Warning::UnevenFaces { .. } => WarningKind::UnevenFaces,
+            Warning::UnknownCustomDie { .. } => WarningKind::UnknownCustomDie,
+            Warning::CustomDieRows { .. } => WarningKind::CustomDieRows,
+            Warning::PairRows { .. } => WarningKind::PairRows,
+            Warning::MalformedHtmlTable { .. } => WarningKind::MalformedHtmlTable,
+            Warning::UnevenDefaultDie { .. } => WarningKind::UnevenDefaultDie,
+            Warning::MismatchedColumns { .. } => WarningKind::MismatchedColumns,
+            Warning::UnclosedProtectedRegion { .. } => WarningKind::UnclosedProtectedRegion,
+            Warning::UnmatchedProtectedEnd { .. } => WarningKind::UnmatchedProtectedEnd,
+            Warning::TableInHtml => WarningKind::TableInHtml,
+        }
+    }
+
+    /// What to do about it, if there is something to suggest.
+    fn suggestion(&self) -> Option<String> {
+        match self {
+            Warning::UnusualDie { suggestion, .. } => suggestion.clone(),
+            Warning::InvalidDie { suggestion, .. } => Some(suggestion.clone()),
+            Warning::TableInHtml => Some(
+                "`before = [\"<name>\"]` in [preprocessor.rolltables] runs this one first".into(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::Truncated => write!(f, "Table ends unexpectedly, leaving it unconverted"),
+            Warning::NoDieForOverflow { rows } => write!(
+                f,
+                "Roll table left unconverted: no standard die has {} faces for the `*` row",
+                rows
+            ),
+            Warning::NoFacesForOverflow => write!(
+                f,
+                "No faces are left for the `*` row of a roll table, removing it"
+            ),
+            Warning::LabelCount { labels, rows } => write!(
+                f,
+                "Roll table left unconverted: generated {} labels for {} rows",
+                labels, rows
+            ),
+            Warning::EmptyLabel => {
+                write!(f, "Roll table left unconverted: generated an empty label")
+            }
+            Warning::Duplicate { text, faces } => write!(
+                f,
+                "Roll table has \"{}\" more than once ({})",
+                text,
+                faces.join("; ")
+            ),
+            Warning::AmbiguousSeparator { die } => write!(
+                f,
+                "Labels of a {} roll table are ambiguous with an empty separator",
+                die
+            ),
+            Warning::AmbiguousHeadSeparator { die } => write!(
+                f,
+                "Header {} of a roll table is ambiguous with an empty head-separator",
+                die
+            ),
+            Warning::UnusualDie { die, suggestion } => {
+                write!(f, "Roll table created with unusual dice: {}", die)?;
+                match suggestion {
+                    Some(suggestion) => write!(f, "; {}", suggestion),
+                    None => Ok(()),
+                }
+            }
+            Warning::InvalidDie {
+                die,
+                suggestion,
+                rows,
+            } => write!(
+                f,
+                "Roll table left unconverted: {} isn't a die, did you mean {}? \
+                 The table has {}",
+                die,
+                suggestion,
+                count(*rows, "row")
+            ),
+            Warning::TooFewFaces { die, rows } => write!(
+                f,
+                "Roll table left unconverted: {} has fewer faces than the table's {} rows",
+                die, rows
+            ),
+            Warning::TooManyFaces { die } => write!(
+                f,
+                "Roll table left unconverted: {} has too many faces to write out",
+                die
+            ),
+            Warning::UnevenFaces { die, rows } => write!(
+                f,
+                "The faces of {} can't be split evenly between {} rows, \
+                 the first rows get one more",
+                die, rows
+            ),
+            Warning::UnknownCustomDie { name } => write!(
+                f,
+                "Roll table left unconverted: custom die \"{}\" isn't in custom-dice",
+                name
+            ),
+            Warning::CustomDieRows { name, faces, rows } => write!(
+                f,
+                "Roll table left unconverted: custom die \"{}\" has {} \
+                 but the table has {}",
+                name,
+                count(*faces, "different face"),
+                count(*rows, "row")
+            ),
+            Warning::PairRows { die, rows } => write!(
+                f,
+                "Roll table left unconverted: {} needs one row per pair of faces \
+                 but the table has {}",
+                die,
+                count(*rows, "row")
+            ),
+            Warning::MalformedHtmlTable { reason } => {
+                write!(f, "HTML table left unconverted, it has {}", reason)
+            }
+            Warning::UnevenDefaultDie { die, rows, instead } => write!(
+                f,
+                "The faces of the default-die d{} can't be split evenly between {} rows, \
+                 using the {} instead",
+                die, rows, instead
+            ),
+            Warning::MismatchedColumns { head, delimiters } => write!(
+                f,
+                "Roll table left unconverted: its header has {} cells but the row below it {}, \
+                 so it isn't read as a table",
+                head, delimiters
+            ),
+            Warning::UnclosedProtectedRegion { start } => write!(
+                f,
+                "{} isn't closed, so the tables in the rest of the chapter are left as they are",
+                start
+            ),
+            Warning::UnmatchedProtectedEnd { end } => {
+                write!(f, "{} doesn't close a protected region, ignoring it", end)
+            }
+            Warning::TableInHtml => write!(
+                f,
+                "Roll table left unconverted: it's inside HTML, so it isn't read as a table. \
+                 If another preprocessor put it there, `before = [\"<name>\"]` in \
+                 [preprocessor.rolltables] runs this one first"
+            ),
+        }
+    }
+}
+
+/// What a [`Diagnostic`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum WarningKind {
+    /// The events of a table ended early.
+    Truncated,
+    /// No standard die has faces for the `*` row.
+    NoDieForOverflow,
+    /// No faces are left for the `*` row.
+    NoFacesForOverflow,
+    /// The faces don't match the rows.
+    LabelCount,
+    /// A face came out empty.
+    EmptyLabel,
+    /// A result is in the table more than once, with `warn-duplicates`.
+    Duplicate,
+    /// The faces can't be read with an empty `separator`.
+    AmbiguousSeparator,
+    /// The header can't be read with an empty `head-separator`.
+    AmbiguousHeadSeparator,
+    /// A die like a d7, with `warn-unusual-dice`.
+    UnusualDie,
+    /// The header isn't a die.
+    InvalidDie,
+    /// The die has fewer faces than the table has rows.
+    TooFewFaces,
+    /// The die has too many faces to write out.
+    TooManyFaces,
+    /// The faces can't be split evenly between the rows.
+    UnevenFaces,
+    /// A custom die that isn't in `custom-dice`.
+    UnknownCustomDie,
+    /// A custom die with a different number of faces than rows.
+    CustomDieRows,
+    /// A header like `d66` on a table with another number of rows.
+    PairRows,
+    /// An HTML table that can't be read.
+    MalformedHtmlTable,
+    /// The `default-die` can't be split evenly between the rows.
+    UnevenDefaultDie,
+    /// A roll table whose delimiter row doesn't match its header.
+    MismatchedColumns,
+    /// A `protected-regions` start without its end.
+    UnclosedProtectedRegion,
+    /// A `protected-regions` end without its start.
+    UnmatchedProtectedEnd,
+    /// A roll table inside HTML.
+    TableInHtml,
+}
+
+/// A warning found while converting a book, for a [`DiagnosticSink`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Diagnostic {
+    /// The name of the chapter, `None` for [`RollTables::transform_table`](crate::RollTables::transform_table).
+    pub chapter: Option<String>,
+    /// The path of the chapter.
+    pub path: Option<PathBuf>,
+    /// Which table of the chapter it is about, counting every markdown
+    /// table, `rolltable` block and HTML table from 0, if it is about one.
+    pub table: Option<usize>,
+    /// What kind of warning it is.
+    pub kind: WarningKind,
+    /// The message printed for it, e.g. "Roll table created with unusual
+    /// dice: d7".
+    pub message: String,
+    /// What to do about it, e.g. which standard dice are a few rows away.
+    pub suggestion: Option<String>,
+}
+
+/// Gets the warnings of a book, each one as soon as it is found, instead of
+/// them being printed in one block at the end, see
+/// [`RollTablesBuilder::with_sink`](crate::RollTablesBuilder::with_sink).
+pub trait DiagnosticSink: Send + Sync {
+    /// Called with every warning.
+    fn report(&self, diagnostic: Diagnostic);
+}
+
+/// A [`DiagnosticSink`] sending every warning to the `log` crate, e.g.
+/// when mdBook's own logger is set up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogSink;
+
+impl DiagnosticSink for LogSink {
+    fn report(&self, diagnostic: Diagnostic) {
+        let location = match (&diagnostic.path, &diagnostic.chapter) {
+            (Some(path), _) => display_path(path),
+            (None, Some(chapter)) => format!("chapter \"{}\"", chapter),
+            (None, None) => "<table>".into(),
+        };
+        log::warn!("{} in {}", diagnostic.message, location);
+    }
+}
+
+/// `n` and `noun`, which is plural unless `n` is 1, e.g. "1 row".
+fn count(n: usize, noun: &str) -> String {
+    match n {
+        1 => format!("1 {}", noun),
+        n => format!("{} {}s", n, noun),
+    }
+}
+
+/// The [`DiagnosticSink`] of a book, if it has one.
+#[derive(Clone, Default)]
+pub(crate) struct Sink(Option<Arc<dyn DiagnosticSink>>);
+
+impl Sink {
+    pub(crate) fn new(sink: impl DiagnosticSink + 'static) -> Self {
+        Self(Some(Arc::new(sink)))
+    }
+}
+
+impl fmt::Debug for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.0.is_some() {
+            "Sink(..)"
+        } else {
+            "Sink(None)"
+        })
+    }
+}
+
+/// Two sinks are the same if they are the same object.
+impl PartialEq for Sink {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+}
+
+impl Eq for Sink {}
+
+/// Where the warnings and stats go, see
+/// [`RollTablesBuilder::report_to`](crate::RollTablesBuilder::report_to).
+#[derive(Clone, Default)]
+pub(crate) struct Report(Option<Arc<ReportFn>>);
+
+type ReportFn = dyn Fn(&str) + Send + Sync;
+
+impl Report {
+    pub(crate) fn new(f: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(f)))
+    }
+
+    /// Sends a block of lines at once, so the ones of books converted at
+    /// the same time don't get mixed up on stderr.
+    pub(crate) fn write(&self, lines: &[String]) {
+        let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        match &self.0 {
+            Some(f) => f(&text),
+            // Like `eprintln!`, there is nowhere to report failing to write.
+            None => drop(io::stderr().lock().write_all(text.as_bytes())),
+        }
+    }
+}
+
+impl fmt::Debug for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.0.is_some() {
+            "Report(..)"
+        } else {
+            "Report(stderr)"
+        })
+    }
+}
+
+/// Two reports are the same if they call the same function.
+impl PartialEq for Report {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+}
+
+impl Eq for Report {}
+
+/// How many chapters are listed for a warning before "…and N more".
+const MAX_LOCATIONS: usize = 5;
+
+/// Collects the warnings of a whole book.
+#[derive(Debug, Default)]
+pub(crate) struct Diagnostics {
+    /// Every unique warning with the location of each time it happened, in
+    /// the order they were first found.
+    warnings: Vec<(Warning, Vec<String>)>,
+    /// What `verbose` prints, with where it is about.
+    notes: Vec<String>,
+    /// Where the next warnings are, see [`Self::chapter`] and [`Self::table`].
+    chapter: Option<String>,
+    path: Option<PathBuf>,
+    table: Option<usize>,
+    sink: Sink,
+}
+
+impl Diagnostics {
+    pub(crate) fn new(sink: &Sink) -> Self {
+        Self {
+            sink: sink.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// The next warnings are about `chapter` but none of its tables.
+    pub(crate) fn chapter(&mut self, chapter: &Chapter) {
+        self.chapter = Some(chapter.name.clone());
+        self.path = chapter.path.clone();
+        self.table = None;
+    }
+
+    /// The next warnings are about the table `index` of the chapter, or
+    /// about none of them.
+    pub(crate) fn table(&mut self, index: Option<usize>) {
+        self.table = index;
+    }
+
+    pub(crate) fn warn(&mut self, warning: Warning, location: &str) {
+        if let Some(sink) = &self.sink.0 {
+            sink.report(Diagnostic {
+                chapter: self.chapter.clone(),
+                path: self.path.clone(),
+                table: self.table,
+                kind: warning.kind(),
+                message: warning.to_string(),
+                suggestion: warning.suggestion(),
+            });
+        }
+        match self.warnings.iter_mut().find(|(w, _)| *w == warning) {
+            Some((_, locations)) => locations.push(location.to_string()),
+            None => self.warnings.push((warning, vec![location.to_string()])),
+        }
+    }
+
+    /// Notes how a table was converted, for `verbose`. Unlike warnings they
+    /// don't fail a `strict` build.
+    pub(crate) fn note(&mut self, note: String, location: &str) {
+        self.notes.push(format!("{} in {}", note, location));
+    }
+
+    /// How many warnings there are, counting each time one happened.
+    pub(crate) fn count(&self) -> usize {
+        self.warnings
+            .iter()
+            .map(|(_, locations)| locations.len())
+            .sum()
+    }
+
+    /// Reports the warnings as one block unless `quiet` is set or they went
+    /// to a [`DiagnosticSink`], or fails listing all of them if `strict` is
+    /// set. The notes of `verbose` come before them.
+    pub(crate) fn finish(self, config: &Config) -> Result<()> {
+        if config.verbose && !config.quiet && !self.notes.is_empty() {
+            let mut lines = vec![format!(
+                "rolltables: {} note{}",
+                self.notes.len(),
+                if self.notes.len() == 1 { "" } else { "s" }
+            )];
+            lines.extend(self.notes.iter().map(|note| format!("  - {}", note)));
+            config.report.write(&lines);
+        }
+        if config.strict && !self.warnings.is_empty() {
+            let lines: Vec<String> = self
+                .warnings
+                .iter()
+                .map(|(warning, locations)| format_warning(warning, locations, usize::MAX))
+                .collect();
+            Err(anyhow!(
+                "Warnings are errors because strict is set:\n{}",
+                lines.join("\n")
+            ))
+        } else {
+            if !config.quiet && self.sink.0.is_none() && !self.warnings.is_empty() {
+                // One block at the end reads better than warnings scattered
+                // through the output of every `mdbook serve` rebuild.
+                let count = self.count();
+                let mut lines = vec![format!(
+                    "rolltables: {} warning{}",
+                    count,
+                    if count == 1 { "" } else { "s" }
+                )];
+                for (warning, locations) in &self.warnings {
+                    lines.push(format!(
+                        "  - {}",
+                        format_warning(warning, locations, MAX_LOCATIONS)
+                    ));
+                }
+                config.report.write(&lines);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// E.g. `Roll table created with unusual dice: d7 (3 times in a.md, b.md)`.
+fn format_warning(warning: &Warning, locations: &[String], max_locations: usize) -> String {
+    let mut unique: Vec<&str> = vec![];
+    for location in locations {
+        if !unique.contains(&location.as_str()) {
+            unique.push(location);
+        }
+    }
+
+    if locations.len() == 1 {
+        return format!("{} in {}", warning, unique[0]);
+    }
+
+    let mut listed = unique
+        .iter()
+        .take(max_locations)
+        .copied()
+        .collect::<Vec<_>>()
+        .join(", ");
+    if unique.len() > max_locations {
+        listed.push_str(&format!(", …and {} more", unique.len() - max_locations));
+    }
+    format!("{} ({} times in {})", warning, locations.len(), listed)
+}
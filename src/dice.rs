@@ -0,0 +1,248 @@
+use crate::MarkerPolicy;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Dice that `warn-unusual-dice` doesn't warn about.
+pub(crate) const STANDARD_DICE: [usize; 7] = [4, 6, 8, 10, 12, 20, 100];
+
+/// The dice paired up for tables without a die of their own, e.g. a d8 and
+/// a d6 for 48 rows.
+pub(crate) const PAIRED_DICE: [usize; 3] = [4, 6, 8];
+
+/// The options that decide the die of a table with a marker, see
+/// [`select_die`]. [`Config::dice_options`](crate::Config::dice_options)
+/// gives the ones of a book.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiceOptions {
+    /// How the marker of the table is read.
+    pub marker: MarkerPolicy,
+    /// `default-die`.
+    pub default_die: Option<usize>,
+    /// `double-small-tables`.
+    pub double_small_tables: Vec<usize>,
+}
+
+/// How the die of a table was picked. [`select_die`] picks a `Die` or a
+/// `Pair` for a number of rows, the others come from the table itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DieChoice {
+    /// A single die whose faces are split evenly between the rows, in order,
+    /// e.g. `Die(20)` for 10 rows gives them `1–2`, `3–4` and so on.
+    Die(usize),
+    /// Two dice read one after the other, one face of each per row, e.g.
+    /// `Pair(6, 6)` for the d66.
+    Pair(usize, usize),
+    /// A die written in the header, e.g. `d20` or `1d8+2`.
+    Explicit,
+    /// The sums of 2d6 shared out by a `bands` preset.
+    Bands,
+    /// A die of `custom-dice`, written `d:name` in the header.
+    Custom,
+    /// A d100 for chances like `15%` in the first column.
+    Chances,
+    /// A die for the rows and one for the columns of a `dxd` table.
+    Matrix,
+}
+
+impl DieChoice {
+    /// How the die `die`, as written in the header, was picked for rows with
+    /// `weights` faces, for messages, e.g. "auto-selected d20 (10 rows, 2
+    /// faces each)", "auto-selected d66 (36 rows)" or "explicit d100 (3 rows,
+    /// 33 to 34 faces)".
+    pub fn describe(&self, die: &str, weights: &[usize]) -> String {
+        let how = match self {
+            Self::Die(_) | Self::Pair(..) | Self::Matrix => "auto-selected",
+            Self::Explicit => "explicit",
+            Self::Bands => "banded",
+            Self::Custom => "custom",
+            Self::Chances => "percentage",
+        };
+        let least = weights.iter().copied().min().unwrap_or(1);
+        let most = weights.iter().copied().max().unwrap_or(1);
+        let faces = match self {
+            // The rows of bands get sums, those of a matrix columns.
+            Self::Bands => String::new(),
+            Self::Matrix => format!(", {} columns", most),
+            _ if most == 1 => String::new(),
+            _ if least == most => format!(", {} faces each", most),
+            _ => format!(", {} to {} faces", least, most),
+        };
+        format!("{} {} ({} rows{})", how, die, weights.len(), faces)
+    }
+}
+
+/// The die a table with `count` rows and a marker gets. The marker wins
+/// over `default-die`, which wins over `double-small-tables`.
+pub fn select_die(count: usize, options: &DiceOptions) -> DieChoice {
+    if let Some(choice) = marker_die(count, options.marker) {
+        return choice;
+    }
+    if let Some(die) = options.default_die.filter(|die| die.is_multiple_of(count)) {
+        return DieChoice::Die(die);
+    }
+    if options.double_small_tables.contains(&count) {
+        if let Some(die) = doubled_die(count) {
+            return DieChoice::Die(die);
+        }
+    }
+
+    match pair(count, &PAIRED_DICE) {
+        Some((a, b)) => DieChoice::Pair(a, b),
+        None => DieChoice::Die(count),
+    }
+}
+
+/// The die `marker` asks for, if it can roll `count` rows.
+pub(crate) fn marker_die(count: usize, marker: MarkerPolicy) -> Option<DieChoice> {
+    match marker {
+        MarkerPolicy::Percentile if 100usize.is_multiple_of(count) => Some(DieChoice::Die(100)),
+        MarkerPolicy::Combination => two_dice(count).map(|(a, b)| DieChoice::Pair(a, b)),
+        _ => None,
+    }
+}
+
+/// The smallest standard die with a multiple of `count` faces, other than
+/// `count` itself, for `double-small-tables`.
+pub(crate) fn doubled_die(count: usize) -> Option<usize> {
+    STANDARD_DICE
+        .iter()
+        .copied()
+        .find(|&die| die > count && die.is_multiple_of(count))
+}
+
+/// The two standard dice, other than the d100, whose faces multiply to
+/// `count`, e.g. a d10 and a d10 for 100.
+fn two_dice(count: usize) -> Option<(usize, usize)> {
+    pair(count, &STANDARD_DICE[..STANDARD_DICE.len() - 1])
+}
+
+/// The two of `dice` whose faces multiply to `count`, the larger one first.
+/// When several pairs fit, the one with the smaller largest die wins, so a
+/// d8 and a d6 rather than a d12 and a d4 for 48, and then the one whose
+/// larger die comes first in `dice`.
+fn pair(count: usize, dice: &[usize]) -> Option<(usize, usize)> {
+    pairs(count, dice).first().copied()
+}
+
+/// Every pair of `dice` for `count` rows, the one [`pair`] picks first.
+fn pairs(count: usize, dice: &[usize]) -> Vec<(usize, usize)> {
+    let mut pairs: Vec<(usize, usize)> = dice
+        .iter()
+        .flat_map(|&a| dice.iter().map(move |&b| (a, b)))
+        .filter(|&(a, b)| a >= b && a * b == count)
+        .collect();
+    pairs.sort_by_key(|&(a, _)| (a, dice.iter().position(|&die| die == a)));
+    pairs
+}
+
+/// The pairs of dice that also fit `count` rows but lose the tie-break to
+/// the one [`select_die`] picks, for `verbose`.
+pub(crate) fn passed_over(count: usize, options: &DiceOptions) -> Vec<(usize, usize)> {
+    let dice = match select_die(count, options) {
+        DieChoice::Pair(..) if marker_die(count, options.marker).is_some() => {
+            &STANDARD_DICE[..STANDARD_DICE.len() - 1]
+        }
+        DieChoice::Pair(..) => &PAIRED_DICE[..],
+        _ => return vec![],
+    };
+    pairs(count, dice).into_iter().skip(1).collect()
+}
+
+/// How a converted table is rolled. It is shown as a dice expression that
+/// virtual tabletops can evaluate, e.g. `1d8+2`, `2d6` or `1d6*10+1d6` for
+/// the d66.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Formula {
+    /// Dice added up, plus a modifier, e.g. one d8 plus 2.
+    Sum {
+        /// How many dice are rolled.
+        dice: usize,
+        /// The faces of each of them.
+        sides: usize,
+        /// Added to the sum.
+        modifier: i64,
+    },
+    /// Two dice read one after the other, the first one giving the tens if
+    /// the second has fewer than 10 faces, e.g. `34` for a 3 and a 4. With
+    /// more, the result counts through the pairs, so a 2 and a 5 with a d10
+    /// for the second die is 15.
+    Pair(usize, usize),
+}
+
+impl Formula {
+    /// A single die with `sides` faces.
+    pub(crate) fn die(sides: usize) -> Self {
+        Self::Sum {
+            dice: 1,
+            sides,
+            modifier: 0,
+        }
+    }
+
+    /// The lowest and highest result of a face as written in a die column,
+    /// e.g. `3–5`, `00` of a d100, or `3.4` of a d4.d6 with a `.` separator.
+    pub(crate) fn bounds(
+        &self,
+        face: &str,
+        range_separator: &str,
+        separator: &str,
+    ) -> Option<(i64, i64)> {
+        let (first, last) = face.split_once(range_separator).unwrap_or((face, face));
+        Some((
+            self.value(first.trim(), separator)?,
+            self.value(last.trim(), separator)?,
+        ))
+    }
+
+    fn value(&self, face: &str, separator: &str) -> Option<i64> {
+        match *self {
+            // The 100 of a d100 is written `00`.
+            Self::Sum {
+                dice: 1,
+                sides: 100,
+                modifier: 0,
+            } if face == "00" => Some(100),
+            Self::Sum { .. } => face.parse().ok(),
+            Self::Pair(_, second) => {
+                let (a, b) = if separator.is_empty() {
+                    // Without a separator only a one digit second die can be
+                    // told apart.
+                    if second >= 10 || face.len() < 2 {
+                        return None;
+                    }
+                    face.split_at(face.len() - 1)
+                } else {
+                    face.split_once(separator)?
+                };
+                let (a, b): (i64, i64) = (a.parse().ok()?, b.parse().ok()?);
+                if second < 10 {
+                    Some(a * 10 + b)
+                } else {
+                    Some((a - 1) * second as i64 + b)
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Sum {
+                dice,
+                sides,
+                modifier,
+            } => {
+                write!(f, "{}d{}", dice, sides)?;
+                if modifier != 0 {
+                    write!(f, "{:+}", modifier)?;
+                }
+                Ok(())
+            }
+            Self::Pair(first, second) if second < 10 => {
+                write!(f, "1d{}*10+1d{}", first, second)
+            }
+            Self::Pair(first, second) => write!(f, "(1d{}-1)*{}+1d{}", first, second, second),
+        }
+    }
+}
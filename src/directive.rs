@@ -0,0 +1,355 @@
+use crate::config::{Config, MarkerPolicy};
+use crate::MarkdownTable;
+use anyhow::{anyhow, Result};
+
+/// A comment like `<!-- rolltables: define weather -->` controlling the
+/// preprocessor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Directive {
+    /// Saves the table that follows under a name.
+    Define(String),
+    /// Replaced by the table saved under a name.
+    Use(String),
+    /// Leaves the table that follows as it is.
+    Skip,
+    /// Copies the table that follows to the `screen-chapter`.
+    Screen,
+    /// Names the table that follows instead of its heading, e.g.
+    /// `name="Forest encounters"`.
+    Name(String),
+    /// Rolls the table that follows with 2d6, giving the rows the sums of a
+    /// preset from `bands`, e.g. `bands=reaction`.
+    Bands(String),
+    /// Leaves the first rows of the table that follows out of the roll
+    /// table, e.g. a bold row naming groups of columns.
+    SkipRows(usize),
+    /// Gives the last row of the table that follows the highest faces of
+    /// the die, e.g. `reserve-top=2` for "99–00: roll twice".
+    ReserveTop(usize),
+    /// Adds a die column to the table that follows if it has none.
+    AddDieColumn,
+    /// Sorts the rows of the table that follows by the text of a column,
+    /// counted from 1, before they get faces.
+    Sort { column: usize, descending: bool },
+    /// Shuffles the rows of the table that follows before they get faces,
+    /// the same way for the same seed, e.g. `shuffle=42`.
+    Shuffle(u64),
+    /// Breaks the text of a column, counted from 1, with `<br>` so its
+    /// lines are about `width` characters, e.g. `wrap-col=2:40`.
+    WrapColumn { column: usize, width: usize },
+    /// Applies options to every table after it in the chapter, e.g.
+    /// `chapter percentile bands=reaction`. Later ones add to it.
+    Chapter(Vec<ChapterOption>),
+}
+
+/// An option of a `chapter` directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ChapterOption {
+    /// How the die of "d" tables is chosen, `auto`, `percentile` or
+    /// `combination` like in `markers`.
+    Marker(MarkerPolicy),
+    /// Like a `bands=` directive before every "d" table, unless the table
+    /// has one of its own.
+    Bands(String),
+    /// Like a `screen` directive before every table.
+    Screen,
+    /// Like an `add-die-column` directive before every table.
+    AddDieColumn,
+}
+
+impl Directive {
+    /// Returns `None` if `html` isn't a rolltables comment.
+    pub(crate) fn parse(html: &str) -> Result<Option<Self>> {
+        let body = match html
+            .trim()
+            .strip_prefix("<!--")
+            .and_then(|s| s.strip_suffix("-->"))
+            .and_then(|s| s.trim().strip_prefix("rolltables:"))
+        {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+
+        let tokens = tokenize(body)?;
+        let directive = match tokens.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["chapter"] => Err(anyhow!("chapter needs at least one option"))?,
+            ["chapter", ref options @ ..] => Directive::Chapter(
+                options
+                    .iter()
+                    .map(|option| chapter_option(option))
+                    .collect::<Result<_>>()?,
+            ),
+            ["define", name] => Directive::Define(name.into()),
+            ["use", name] => Directive::Use(name.into()),
+            ["skip"] => Directive::Skip,
+            ["screen"] => Directive::Screen,
+            ["add-die-column"] => Directive::AddDieColumn,
+            [name] if name.starts_with("name=") => match name["name=".len()..].trim() {
+                "" => Err(anyhow!("name can't be empty"))?,
+                name => Directive::Name(name.into()),
+            },
+            [bands] if bands.starts_with("bands=") => match bands["bands=".len()..].trim() {
+                "" => Err(anyhow!("bands needs the name of a preset"))?,
+                name => Directive::Bands(name.into()),
+            },
+            [reserve] if reserve.starts_with("reserve-top=") => {
+                match reserve["reserve-top=".len()..].parse() {
+                    Ok(n) if n > 0 => Directive::ReserveTop(n),
+                    _ => Err(anyhow!(
+                        "reserve-top must be a positive integer, not \"{}\"",
+                        &reserve["reserve-top=".len()..]
+                    ))?,
+                }
+            }
+            [shuffle] if shuffle.starts_with("shuffle=") => {
+                match shuffle["shuffle=".len()..].parse() {
+                    Ok(seed) => Directive::Shuffle(seed),
+                    _ => Err(anyhow!(
+                        "shuffle needs a seed like shuffle=42, not \"{}\"",
+                        &shuffle["shuffle=".len()..]
+                    ))?,
+                }
+            }
+            [wrap] if wrap.starts_with("wrap-col=") => {
+                let value = &wrap["wrap-col=".len()..];
+                match value.split_once(':').map(|(c, w)| (c.parse(), w.parse())) {
+                    Some((Ok(column), Ok(width))) if column > 0 && width > 0 => {
+                        Directive::WrapColumn { column, width }
+                    }
+                    _ => Err(anyhow!(
+                        "wrap-col needs a column and a width like wrap-col=2:40, not \"{}\"",
+                        value
+                    ))?,
+                }
+            }
+            ["skip-rows", n] => match n.parse() {
+                Ok(n) if n > 0 => Directive::SkipRows(n),
+                _ => Err(anyhow!(
+                    "skip-rows must be a positive integer, not \"{}\"",
+                    n
+                ))?,
+            },
+            ["sort"] => Directive::Sort {
+                column: 2,
+                descending: false,
+            },
+            ["sort", order] | ["sort", order, _] => {
+                let descending = match order {
+                    "asc" => false,
+                    "desc" => true,
+                    _ => Err(anyhow!("sort order must be asc or desc, not \"{}\"", order))?,
+                };
+                let column = match tokens.get(2).map(|n| n.parse()) {
+                    None => 2,
+                    Some(Ok(n)) if n > 0 => n,
+                    Some(_) => Err(anyhow!(
+                        "sort column must be a positive integer, not \"{}\"",
+                        tokens[2]
+                    ))?,
+                };
+                Directive::Sort { column, descending }
+            }
+            _ => Err(anyhow!("unknown directive \"{}\"", body.trim()))?,
+        };
+
+        Ok(Some(directive))
+    }
+}
+
+/// The directives before a table, waiting for it. Each of them applies to
+/// that table only.
+#[derive(Debug, Default)]
+pub(crate) struct PendingDirectives {
+    pub(crate) define: Option<String>,
+    pub(crate) skip: bool,
+    pub(crate) screen: bool,
+    pub(crate) name: Option<String>,
+    pub(crate) add_die_column: bool,
+    skip_rows: Option<usize>,
+    pub(crate) reserve_top: Option<usize>,
+    pub(crate) bands: Option<String>,
+    sort: Option<(usize, bool)>,
+    shuffle: Option<u64>,
+    wrap_columns: Vec<(usize, usize)>,
+}
+
+impl PendingDirectives {
+    /// Keeps `directive` for the next table. `use` and `chapter` don't wait
+    /// for one and are left alone.
+    pub(crate) fn push(&mut self, directive: Directive) {
+        match directive {
+            Directive::Define(name) => self.define = Some(name),
+            Directive::Skip => self.skip = true,
+            Directive::Screen => self.screen = true,
+            Directive::Name(name) => self.name = Some(name),
+            Directive::AddDieColumn => self.add_die_column = true,
+            Directive::SkipRows(n) => self.skip_rows = Some(n),
+            Directive::ReserveTop(n) => self.reserve_top = Some(n),
+            Directive::Bands(name) => self.bands = Some(name),
+            Directive::Sort { column, descending } => self.sort = Some((column, descending)),
+            Directive::Shuffle(seed) => self.shuffle = Some(seed),
+            Directive::WrapColumn { column, width } => self.wrap_columns.push((column, width)),
+            Directive::Use(_) | Directive::Chapter(_) => {}
+        }
+    }
+
+    /// Fails if a directive is waiting where something other than a table
+    /// follows it.
+    pub(crate) fn check_followed(&self, location: &str) -> Result<()> {
+        let waiting = [
+            ("define", self.define.is_some(), self.define.as_ref()),
+            ("skip", self.skip, None),
+            ("screen", self.screen, None),
+            ("name", self.name.is_some(), self.name.as_ref()),
+            ("bands", self.bands.is_some(), self.bands.as_ref()),
+            ("skip-rows", self.skip_rows.is_some(), None),
+            ("reserve-top", self.reserve_top.is_some(), None),
+            ("add-die-column", self.add_die_column, None),
+            ("sort", self.sort.is_some(), None),
+            ("shuffle", self.shuffle.is_some(), None),
+            ("wrap-col", !self.wrap_columns.is_empty(), None),
+        ];
+        match waiting.iter().find(|(_, waiting, _)| *waiting) {
+            Some((directive, _, Some(name))) => Err(anyhow!(
+                "The {} directive for \"{}\" in {} isn't followed by a table",
+                directive,
+                name,
+                location
+            )),
+            Some((directive, _, None)) => Err(anyhow!(
+                "The {} directive in {} isn't followed by a table",
+                directive,
+                location
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Leaves the rows of `skip-rows` out of `table`.
+    pub(crate) fn skip_rows(&self, table: &mut MarkdownTable, location: &str) -> Result<()> {
+        if let Some(n) = self.skip_rows {
+            if n >= table.rows().len() {
+                Err(anyhow!(
+                    "skip-rows {} in {} leaves no rows in the table",
+                    n,
+                    location
+                ))?;
+            }
+            table.skip_rows(n);
+        }
+        Ok(())
+    }
+
+    /// Whether `sort` changes the order of the rows.
+    pub(crate) fn sorts(&self) -> bool {
+        self.sort.is_some()
+    }
+
+    /// Sorts or shuffles the rows of `table`. Returns the table as it was
+    /// before it was shuffled, for if it doesn't get faces: a table
+    /// converted before keeps its order.
+    pub(crate) fn order_rows<'a>(
+        &self,
+        table: &mut MarkdownTable<'a>,
+        config: &Config,
+        location: &str,
+    ) -> Result<Option<MarkdownTable<'a>>> {
+        if self.sort.is_some() && self.shuffle.is_some() {
+            Err(anyhow!(
+                "sort and shuffle in {} can't both be used for one table",
+                location
+            ))?;
+        }
+        let unshuffled = self.shuffle.map(|seed| {
+            let unshuffled = table.clone();
+            table.shuffle_rows(seed);
+            unshuffled
+        });
+        if let Some((column, descending)) = self.sort {
+            if column > table.head().len() {
+                Err(anyhow!(
+                    "sort {} in {} but the table has {} columns",
+                    column,
+                    location,
+                    table.head().len()
+                ))?;
+            }
+            table.sort_rows(column - 1, descending, config.sort_ignore_case);
+        }
+        Ok(unshuffled)
+    }
+
+    /// Fails if a `wrap-col` is past the last column of `table`.
+    pub(crate) fn check_wrap_columns(&self, table: &MarkdownTable, location: &str) -> Result<()> {
+        match self
+            .wrap_columns
+            .iter()
+            .find(|(column, _)| *column > table.head().len())
+        {
+            Some((column, _)) => Err(anyhow!(
+                "wrap-col={} in {} but the table has {} columns",
+                column,
+                location,
+                table.head().len()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Wraps the columns of `wrap-col`, returns whether any cell changed.
+    /// Like sorting, this also applies to tables converted before, which
+    /// are already wrapped.
+    pub(crate) fn wrap_columns(&self, table: &mut MarkdownTable) -> bool {
+        let mut wrapped = false;
+        for &(column, width) in &self.wrap_columns {
+            wrapped |= table.wrap_column(column - 1, width);
+        }
+        wrapped
+    }
+}
+
+fn chapter_option(option: &str) -> Result<ChapterOption> {
+    Ok(match option {
+        "auto" => ChapterOption::Marker(MarkerPolicy::Auto),
+        "percentile" => ChapterOption::Marker(MarkerPolicy::Percentile),
+        "combination" => ChapterOption::Marker(MarkerPolicy::Combination),
+        "screen" => ChapterOption::Screen,
+        "add-die-column" => ChapterOption::AddDieColumn,
+        bands if bands.starts_with("bands=") => match bands["bands=".len()..].trim() {
+            "" => Err(anyhow!("bands needs the name of a preset"))?,
+            name => ChapterOption::Bands(name.into()),
+        },
+        _ => Err(anyhow!("unknown chapter option \"{}\"", option))?,
+    })
+}
+
+/// Splits on whitespace, keeping "quoted strings" together.
+pub(crate) fn tokenize(s: &str) -> Result<Vec<String>> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+
+    if quoted {
+        Err(anyhow!(
+            "unclosed quote in rolltables directive: {}",
+            s.trim()
+        ))?;
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
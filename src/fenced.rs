@@ -0,0 +1,104 @@
+use crate::{directive::tokenize, table::MarkdownTable};
+use anyhow::{anyhow, Result};
+use pulldown_cmark::{Alignment, Event};
+
+/// Whether a fenced code block is a roll table written one entry per line,
+/// i.e. its info string starts with `rolltable`.
+pub(crate) fn is_rolltable(info: &str) -> bool {
+    info.split_whitespace().next() == Some("rolltable")
+}
+
+/// Builds the table of a `rolltable` block, with a "d" header and an empty
+/// first column so it's converted like the tables written by hand. Every
+/// line that isn't empty or a `#` comment is a row, `3: text` giving it
+/// three faces. The entries are plain text, so `*` or `|` in them is
+/// escaped when the table is written.
+pub(crate) fn parse_block<'a>(
+    info: &str,
+    code: &str,
+    delimiter: &str,
+) -> Result<MarkdownTable<'a>> {
+    let mut header = vec![];
+    for option in tokenize(info)?.iter().skip(1) {
+        match option.split_once('=') {
+            Some(("header", text)) => header = split_cells(text, delimiter),
+            _ => Err(anyhow!("unknown rolltable option \"{}\"", option))?,
+        }
+    }
+
+    let mut rows = vec![];
+    let mut weights = vec![];
+    for line in code.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (weight, entry) = match line.split_once(':') {
+            Some((weight, entry))
+                if !weight.is_empty() && weight.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                match weight.parse() {
+                    Ok(weight) if weight > 0 => (weight, entry),
+                    _ => Err(anyhow!(
+                        "the weight of \"{}\" must be a positive integer",
+                        entry.trim()
+                    ))?,
+                }
+            }
+            _ => (1, line),
+        };
+        rows.push(split_cells(entry, delimiter));
+        weights.push(weight);
+    }
+
+    if rows.is_empty() {
+        Err(anyhow!("the rolltable block has no entries"))?;
+    }
+
+    let columns = 1 + rows
+        .iter()
+        .chain(Some(&header))
+        .map(Vec::len)
+        .max()
+        .unwrap_or(0);
+    let text_cells = |cells: Vec<String>| -> Vec<Vec<Event<'a>>> {
+        let mut row: Vec<Vec<Event>> = cells
+            .into_iter()
+            .map(|cell| {
+                if cell.is_empty() {
+                    vec![]
+                } else {
+                    vec![Event::Text(cell.into())]
+                }
+            })
+            .collect();
+        row.resize(columns - 1, vec![]);
+        row
+    };
+
+    let head = Some(vec![Event::Text("d".into())])
+        .into_iter()
+        .chain(text_cells(header))
+        .collect();
+    let content = Some(head)
+        .into_iter()
+        .chain(
+            rows.into_iter()
+                .map(|cells| Some(vec![]).into_iter().chain(text_cells(cells)).collect()),
+        )
+        .collect();
+    // Without weights the table is the same as one written by hand.
+    let weights = Some(weights).filter(|weights| weights.iter().any(|&w| w > 1));
+
+    Ok(MarkdownTable::from_content(
+        vec![Alignment::None; columns],
+        content,
+        weights,
+    ))
+}
+
+fn split_cells(text: &str, delimiter: &str) -> Vec<String> {
+    text.split(delimiter)
+        .map(|cell| cell.trim().to_string())
+        .collect()
+}
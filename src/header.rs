@@ -0,0 +1,186 @@
+use crate::dice::PAIRED_DICE;
+use std::ops::Range;
+
+/// A die written explicitly in the header instead of the "d" marker, like
+/// `d20`, `1d20` or `1d8+2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ExplicitDie {
+    pub(crate) sides: u64,
+    /// Added to every face.
+    pub(crate) modifier: i64,
+}
+
+impl ExplicitDie {
+    /// Returns `None` if `header` isn't a die expression.
+    pub(crate) fn parse(header: &str) -> Option<Self> {
+        let header = header.strip_prefix('1').unwrap_or(header);
+        let rest = header.strip_prefix('d')?;
+
+        let (sides, modifier) = match rest.find(['+', '-'].as_ref()) {
+            Some(i) => (&rest[..i], Some(&rest[i..])),
+            None => (rest, None),
+        };
+        let modifier = match modifier {
+            Some(modifier) => {
+                let digits = &modifier[1..];
+                if !is_number(digits) {
+                    return None;
+                }
+                modifier.parse().ok()?
+            }
+            None => 0,
+        };
+        if !is_number(sides) {
+            return None;
+        }
+
+        Some(Self {
+            sides: sides.parse().ok()?,
+            modifier,
+        })
+    }
+}
+
+/// The two dice of a header like `d66` or `d86`, or `d6.6` with a `.`
+/// head-separator, read one after the other. Only the dice that are paired
+/// up for tables without a die count, so `d20` stays a single die.
+pub(crate) fn explicit_pair(header: &str, head_separator: &str) -> Option<(usize, usize)> {
+    let rest = header.strip_prefix('d')?;
+    let mut chars = rest.chars();
+    let first = chars.next()?.to_digit(10)? as usize;
+    let rest = chars.as_str();
+    let rest = rest.strip_prefix(head_separator).unwrap_or(rest);
+    let second: usize = match rest.len() {
+        1 => rest.parse().ok()?,
+        _ => return None,
+    };
+    if PAIRED_DICE.contains(&first) && PAIRED_DICE.contains(&second) {
+        Some((first, second))
+    } else {
+        None
+    }
+}
+
+/// Byte ranges of the dice expressions like `d6`, `2d6` or `1d8+2` in prose.
+/// They have to be whole words, so the "d10" of "wind10" isn't one.
+pub(crate) fn find_dice(text: &str) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    let is_word = |i: usize| {
+        bytes
+            .get(i)
+            .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_')
+    };
+    let digits = |mut i: usize| {
+        while bytes.get(i).is_some_and(u8::is_ascii_digit) {
+            i += 1;
+        }
+        i
+    };
+
+    let mut found = vec![];
+    let mut start = 0;
+    while start < bytes.len() {
+        if start > 0 && is_word(start - 1) || !is_word(start) {
+            start += 1;
+            continue;
+        }
+
+        let d = digits(start);
+        let sides_end = if bytes.get(d) == Some(&b'd') {
+            digits(d + 1)
+        } else {
+            d + 1
+        };
+        if sides_end > d + 1 {
+            let mut end = sides_end;
+            if matches!(bytes.get(end), Some(b'+') | Some(b'-')) && digits(end + 1) > end + 1 {
+                end = digits(end + 1);
+            }
+            if !is_word(end) {
+                found.push(start..end);
+                start = end;
+                continue;
+            }
+        }
+
+        // Skips the rest of the word.
+        while is_word(start) {
+            start += 1;
+        }
+    }
+
+    found
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// The die that was most likely meant instead of `written`, e.g. d10 for
+/// d1. Of the dice a typo or two away the one with as many faces as the
+/// table has rows is preferred, then the closest one.
+pub(crate) fn suggest_die(written: &str, rows: usize, standard: &[usize]) -> String {
+    let mut candidates: Vec<usize> = standard.to_vec();
+    if !candidates.contains(&rows) && rows >= 2 {
+        candidates.push(rows);
+    }
+
+    candidates
+        .into_iter()
+        .map(|sides| (edit_distance(written, &format!("d{}", sides)), sides))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|&(distance, sides)| (sides != rows, distance, sides))
+        .map_or_else(|| format!("d{}", rows), |(_, sides)| format!("d{}", sides))
+}
+
+/// How to change `count` rows, or columns, to get the closest smaller and
+/// larger standard dice, e.g. "remove 1 row for d6, or add 1 for d8" for 7.
+pub(crate) fn standard_alternatives(count: usize, unit: &str, standard: &[usize]) -> String {
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    let smaller = standard.iter().filter(|&&die| die < count).max();
+    let larger = standard.iter().filter(|&&die| die > count).min();
+
+    match (smaller, larger) {
+        (Some(&smaller), Some(&larger)) => format!(
+            "remove {} {}{} for d{}, or add {} for d{}",
+            count - smaller,
+            unit,
+            plural(count - smaller),
+            smaller,
+            larger - count,
+            larger
+        ),
+        (Some(&smaller), None) => format!(
+            "remove {} {}{} for d{}",
+            count - smaller,
+            unit,
+            plural(count - smaller),
+            smaller
+        ),
+        (None, Some(&larger)) => format!(
+            "add {} {}{} for d{}",
+            larger - count,
+            unit,
+            plural(larger - count),
+            larger
+        ),
+        (None, None) => String::new(),
+    }
+}
+
+/// Levenshtein distance counting characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + (ca != *cb) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
@@ -0,0 +1,117 @@
+use std::ops::Range;
+
+/// A raw HTML `<table>` whose first header cell is "d", for
+/// `html-table-support`. Only the byte ranges of the contents of the cells
+/// are kept, the rest of the HTML is copied as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HtmlTable {
+    /// The header cells, the first one being the "d".
+    pub(crate) head: Vec<Range<usize>>,
+    /// The cells of every row below the header, the first one being empty.
+    pub(crate) rows: Vec<Vec<Range<usize>>>,
+}
+
+#[derive(Debug)]
+struct Cell {
+    header: bool,
+    rowspan: bool,
+    content: Range<usize>,
+}
+
+impl HtmlTable {
+    /// Returns `Ok(None)` if `html` isn't a roll table, and why it can't be
+    /// read if it's too malformed to tell.
+    pub(crate) fn parse(html: &str) -> Result<Option<Self>, &'static str> {
+        let mut rows: Vec<Vec<Cell>> = vec![];
+        let mut open: Option<Cell> = None;
+        let mut tables = 0;
+        let mut i = 0;
+
+        while let Some(start) = html[i..].find('<').map(|s| s + i) {
+            if html[start..].starts_with("<!--") {
+                i = match html[start..].find("-->") {
+                    Some(end) => start + end + 3,
+                    None => return Err("unclosed comment"),
+                };
+                continue;
+            }
+            let end = match html[start..].find('>') {
+                Some(end) => start + end,
+                None => return Err("unclosed tag"),
+            };
+            i = end + 1;
+
+            let tag = html[start + 1..end].trim_end_matches('/');
+            let (closing, tag) = match tag.strip_prefix('/') {
+                Some(tag) => (true, tag),
+                None => (false, tag),
+            };
+            let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+            let (name, attributes) = tag.split_at(name_end);
+
+            match (name.to_ascii_lowercase().as_str(), closing) {
+                ("table", false) => {
+                    tables += 1;
+                    if tables > 1 {
+                        return Err("nested tables");
+                    }
+                }
+                ("table", true) => tables -= 1,
+                ("tr", false) => {
+                    if open.is_some() {
+                        return Err("a cell without a closing tag");
+                    }
+                    rows.push(vec![]);
+                }
+                (cell @ "th", false) | (cell @ "td", false) => {
+                    if open.is_some() {
+                        return Err("a cell without a closing tag");
+                    }
+                    open = Some(Cell {
+                        header: cell == "th",
+                        rowspan: attributes.to_ascii_lowercase().contains("rowspan"),
+                        content: i..i,
+                    });
+                }
+                ("th", true) | ("td", true) => match (open.take(), rows.last_mut()) {
+                    (Some(mut cell), Some(row)) => {
+                        cell.content.end = start;
+                        row.push(cell);
+                    }
+                    _ => return Err("a cell outside of a row"),
+                },
+                _ => {}
+            }
+        }
+
+        if tables != 0 || open.is_some() {
+            return Err("unclosed tags");
+        }
+
+        let mut rows = rows.into_iter().filter(|row| !row.is_empty());
+        let head = match rows.next() {
+            Some(row) if row[0].header && html[row[0].content.clone()].trim() == "d" => {
+                row.into_iter().map(|cell| cell.content).collect()
+            }
+            _ => return Ok(None),
+        };
+
+        let mut body = vec![];
+        for row in rows {
+            let cell = &row[0];
+            if cell.header || !html[cell.content.clone()].trim().is_empty() {
+                return Ok(None);
+            }
+            if cell.rowspan {
+                return Err("a rowspan in the first column");
+            }
+            body.push(row.into_iter().map(|cell| cell.content).collect());
+        }
+
+        if body.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self { head, rows: body }))
+    }
+}
@@ -1,182 +1,2906 @@
 #![warn(missing_docs)]
-#![doc = include_str!("../README.md")]
+#![cfg_attr(feature = "mdbook", doc = include_str!("../README.md"))]
+#![cfg_attr(
+    not(feature = "mdbook"),
+    doc = "The conversion of roll tables of mdbook-rolltables, built without the `mdbook` \
+           feature. [`RollTables::process_markdown`] converts a markdown document."
+)]
 
-use anyhow::anyhow;
+use anyhow::Result;
+#[cfg(not(feature = "mdbook"))]
+use book::{Book, BookItem, Chapter};
+#[cfg(feature = "mdbook")]
 use mdbook::{
     book::{Book, Chapter},
-    errors::Result,
     preprocess::{Preprocessor, PreprocessorContext},
     BookItem,
 };
-use pulldown_cmark::{Alignment, Event, Options, Parser, Tag};
-use pulldown_cmark_to_cmark::cmark;
-use std::iter;
-use toml::Value;
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, Options, Parser, Tag};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    iter,
+    ops::Range,
+    path::Path,
+};
+
+#[cfg(not(feature = "mdbook"))]
+mod book;
+mod cache;
+#[cfg(feature = "mdbook")]
+mod cli;
+mod config;
+mod diagnostics;
+mod dice;
+mod directive;
+mod fenced;
+mod header;
+mod html;
+mod metadata;
+mod model;
+mod subroll;
+mod table;
+mod text;
+
+use anyhow::anyhow;
+use cache::{Cache, ConvertHook};
+#[cfg(feature = "mdbook")]
+pub use cli::run_cli;
+pub use config::{
+    CenterDieColumn, ColumnAlignment, Config, D66Dice, DieMarkup, FaceStyle, FaceWrap,
+    KeepOriginal, MarkerPolicy, MergeDuplicates, ProseDice, UnusualDice,
+};
+pub use diagnostics::{Diagnostic, DiagnosticSink, LogSink, WarningKind};
+use diagnostics::{Diagnostics, Report, Sink, Warning};
+use dice::{marker_die, passed_over, STANDARD_DICE};
+pub use dice::{select_die, DiceOptions, DieChoice, Formula};
+use directive::{ChapterOption, Directive, PendingDirectives};
+use header::{explicit_pair, find_dice, standard_alternatives, suggest_die, ExplicitDie};
+use html::HtmlTable;
+#[cfg(feature = "mdbook")]
+use metadata::{inconsistent_dice, write_manifest};
+use metadata::{metadata_comment, print_stats, screen_chapter, ScreenTable, TableInfo, TableNames};
+use model::{faces, markdown_events};
+pub use model::{FaceBounds, RollRow, RollTable};
+/// The version of pulldown-cmark whose events make up a [`MarkdownTable`].
+pub use pulldown_cmark;
+use subroll::number_sub_rolls;
+pub use table::MarkdownTable;
+use table::{
+    cell_text, escape_label, inline_to_markdown, is_blank, mismatched_head, plain_text,
+    replace_plain_text, split_annotation, stacked_heads, strip_annotation, table_head,
+};
+use text::fold_case;
+
+/// The struct that implements Preprocessor trait.
+///
+/// Use [`RollTables::default`] to read all options from `book.toml`, or
+/// [`RollTablesBuilder`] to set some of them programmatically.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RollTables {
+    overrides: RollTablesBuilder,
+}
+
+impl RollTables {
+    /// Creates a [`RollTablesBuilder`].
+    pub fn builder() -> RollTablesBuilder {
+        RollTablesBuilder::default()
+    }
+
+    /// Converts the roll tables of a single markdown document, without
+    /// mdBook. `cfg` takes the place of `[preprocessor.rolltables]`.
+    ///
+    /// ```
+    /// use mdbook_rolltables::RollTables;
+    ///
+    /// let table = "| d | Weather |\n|---|---|\n| | Rain |\n| | Sun |\n";
+    /// let output = RollTables::default().process_markdown(table, None).unwrap();
+    /// assert!(output.contains("d2"));
+    /// ```
+    pub fn process_markdown(
+        &self,
+        markdown: &str,
+        cfg: Option<&toml::value::Table>,
+    ) -> Result<String> {
+        let config = Config::new(cfg, &self.overrides)?;
+        if config.disabled {
+            return Ok(markdown.into());
+        }
+
+        let mut book = Book::new();
+        book.push_item(Chapter::new("stdin", markdown.into(), "<stdin>", vec![]));
+        self.process_book(&mut book, &config, &mut |_, _| {}, None)?;
+
+        // The screen-chapter can come after it.
+        let mut output = None;
+        book.for_each_mut(|item| match item {
+            BookItem::Chapter(chapter) if output.is_none() => {
+                output = Some(std::mem::take(&mut chapter.content))
+            }
+            _ => {}
+        });
+        Ok(output.unwrap_or_default())
+    }
+
+    /// Converts a book without keeping the result, returning a message for
+    /// every number of rows that got different dice in different tables,
+    /// e.g. a d66 in one chapter and a d36 in another. `cfg` takes the place
+    /// of `[preprocessor.rolltables]`.
+    #[cfg(feature = "mdbook")]
+    pub fn check_book(
+        &self,
+        mut book: Book,
+        cfg: Option<&toml::value::Table>,
+    ) -> Result<Vec<String>> {
+        let config = Config::new(cfg, &self.overrides)?;
+        let tables = self.process_book(&mut book, &config, &mut |_, _| {}, None)?;
+        Ok(inconsistent_dice(&tables))
+    }
+
+    /// Converts a book without keeping the result, returning every converted
+    /// roll table as plain data, e.g. to export them. `cfg` takes the place
+    /// of `[preprocessor.rolltables]`.
+    #[cfg(feature = "mdbook")]
+    pub fn roll_tables(
+        &self,
+        mut book: Book,
+        cfg: Option<&toml::value::Table>,
+    ) -> Result<Vec<RollTable>> {
+        let config = Config::new(cfg, &self.overrides)?;
+        let tables = self.process_book(&mut book, &config, &mut |_, _| {}, None)?;
+        Ok(tables.into_iter().map(|info| info.table).collect())
+    }
+
+    /// Resolves the options for [`Self::transform_table`]. `cfg` takes the
+    /// place of `[preprocessor.rolltables]`.
+    pub fn config(&self, cfg: Option<&toml::value::Table>) -> Result<Config> {
+        Config::new(cfg, &self.overrides)
+    }
+
+    /// Converts a single table the way the preprocessor converts the tables
+    /// of a chapter, without the directives in front of it. Warnings are
+    /// printed right away, or returned as an error with `strict`.
+    pub fn transform_table(
+        &self,
+        table: &mut MarkdownTable,
+        config: &Config,
+    ) -> Result<TableOutcome> {
+        let mut diagnostics = Diagnostics::new(&config.sink);
+        let outcome = self.transform(table, None, None, "<table>", config, &mut diagnostics)?;
+        diagnostics.finish(config)?;
+        Ok(outcome)
+    }
+
+    /// Converts a book like the preprocessor does, calling `f` with every
+    /// markdown table after it is converted and before it is written, so
+    /// another transformation shares the single parse and serialization of
+    /// the chapter. `cfg` takes the place of `[preprocessor.rolltables]`.
+    ///
+    /// `f` gets unconverted tables too, with [`TableOutcome::Unchanged`].
+    /// It must keep every row as long as the head and one alignment per
+    /// column, which [`MarkdownTable::insert_column`] does, and leave the die
+    /// column first. Changed tables are written back even if they weren't
+    /// converted, except for skipped `rolltable` blocks, which stay code.
+    #[cfg(feature = "mdbook")]
+    pub fn process_with(
+        &self,
+        mut book: Book,
+        cfg: Option<&toml::value::Table>,
+        mut f: impl FnMut(&mut MarkdownTable, &TableOutcome),
+    ) -> Result<Book> {
+        let config = Config::new(cfg, &self.overrides)?;
+        if config.disabled {
+            return Ok(book);
+        }
+        self.process_book(&mut book, &config, &mut f, None)?;
+        Ok(book)
+    }
+}
+
+/// Builder for [`RollTables`] for when mdBook is used as a library.
+///
+/// Every method mirrors a key of `[preprocessor.rolltables]` and values set
+/// here take precedence over the ones in `book.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RollTablesBuilder {
+    head_separator: Option<String>,
+    separator: Option<String>,
+    warn_unusual_dice: Option<UnusualDice>,
+    face_wrap: Option<FaceWrap>,
+    face_wrap_size: Option<usize>,
+    face_style: Option<FaceStyle>,
+    center_die_column: Option<CenterDieColumn>,
+    generated_column_alignment: Option<ColumnAlignment>,
+    strict: Option<bool>,
+    range_separator: Option<String>,
+    quiet: Option<bool>,
+    verbose: Option<bool>,
+    pretty_tables: Option<bool>,
+    stats: Option<bool>,
+    detailed_stats: Option<bool>,
+    warn_duplicates: Option<bool>,
+    duplicates_ignore_case: Option<bool>,
+    duplicates_min_rows: Option<usize>,
+    custom_dice: BTreeMap<String, Vec<String>>,
+    bands: BTreeMap<String, Vec<String>>,
+    markers: BTreeMap<String, MarkerPolicy>,
+    custom_face_label: Option<String>,
+    matrix_corner: Option<String>,
+    manifest: Option<String>,
+    cache: Option<String>,
+    d66_dice: Option<D66Dice>,
+    d66_d10_suffix: Option<String>,
+    convert_in: Option<Vec<String>>,
+    sort_ignore_case: Option<bool>,
+    merge_duplicates: Option<MergeDuplicates>,
+    style_dice_in_prose: Option<ProseDice>,
+    html_table_support: Option<bool>,
+    default_die: Option<usize>,
+    assume_empty_header: Option<bool>,
+    empty_header_min_rows: Option<usize>,
+    double_small_tables: Option<Vec<usize>>,
+    die_markup: Option<DieMarkup>,
+    preserve_line_endings: Option<bool>,
+    rolltable_delimiter: Option<String>,
+    screen_chapter: Option<String>,
+    single_column_output: Option<bool>,
+    single_column_label: Option<String>,
+    emit_metadata: Option<bool>,
+    debug_keep_original: Option<KeepOriginal>,
+    caption_detection: Option<bool>,
+    protected_regions: Option<Vec<(String, String)>>,
+    convert_in_protected: Option<bool>,
+    report: Report,
+    sink: Sink,
+    on_convert: ConvertHook,
+}
+
+impl RollTablesBuilder {
+    /// Creates a builder with no options set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Separator when there are multiple dice e.g. d66 but in the header
+    /// (`head-separator`).
+    pub fn head_separator(mut self, head_separator: impl Into<String>) -> Self {
+        self.head_separator = Some(head_separator.into());
+        self
+    }
+
+    /// Separator when there are multiple dice e.g. d66 (`separator`).
+    pub fn separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = Some(separator.into());
+        self
+    }
+
+    /// Warns about d7, d23 etc. (`warn-unusual-dice`).
+    pub fn warn_unusual_dice(mut self, warn_unusual_dice: UnusualDice) -> Self {
+        self.warn_unusual_dice = Some(warn_unusual_dice);
+        self
+    }
+
+    /// How rows with several faces are laid out (`face-wrap`).
+    pub fn face_wrap(mut self, face_wrap: FaceWrap) -> Self {
+        self.face_wrap = Some(face_wrap);
+        self
+    }
+
+    /// Number of faces between each `<br>` (`face-wrap-size`).
+    pub fn face_wrap_size(mut self, face_wrap_size: usize) -> Self {
+        self.face_wrap_size = Some(face_wrap_size);
+        self
+    }
+
+    /// Whether the faces are written as text or code spans (`face-style`).
+    pub fn face_style(mut self, face_style: FaceStyle) -> Self {
+        self.face_style = Some(face_style);
+        self
+    }
+
+    /// Whether converted tables get a centered die column
+    /// (`center-die-column`).
+    pub fn center_die_column(mut self, center_die_column: CenterDieColumn) -> Self {
+        self.center_die_column = Some(center_die_column);
+        self
+    }
+
+    /// How the columns added to tables are aligned
+    /// (`generated-column-alignment`).
+    pub fn generated_column_alignment(mut self, alignment: ColumnAlignment) -> Self {
+        self.generated_column_alignment = Some(alignment);
+        self
+    }
+
+    /// Fails the build instead of printing warnings (`strict`).
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = Some(strict);
+        self
+    }
+
+    /// Writes consecutive faces as a range like "1–2" instead of "1, 2"
+    /// (`range-separator`).
+    pub fn range_separator(mut self, range_separator: impl Into<String>) -> Self {
+        self.range_separator = Some(range_separator.into());
+        self
+    }
+
+    /// Doesn't print warnings (`quiet`).
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = Some(quiet);
+        self
+    }
+
+    /// Also prints why a pair of dice was picked over the others that fit
+    /// (`verbose`).
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = Some(verbose);
+        self
+    }
+
+    /// Pads the cells of converted tables so the pipes line up in the
+    /// markdown source (`pretty-tables`).
+    pub fn pretty_tables(mut self, pretty_tables: bool) -> Self {
+        self.pretty_tables = Some(pretty_tables);
+        self
+    }
+
+    /// Prints how many tables and results the book has (`stats`).
+    pub fn stats(mut self, stats: bool) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Also prints the least and most likely row of tables whose rows aren't
+    /// equally likely (`stats = "detailed"`).
+    pub fn detailed_stats(mut self, detailed_stats: bool) -> Self {
+        self.detailed_stats = Some(detailed_stats);
+        self
+    }
+
+    /// Warns about rows with the same results (`warn-duplicates`).
+    pub fn warn_duplicates(mut self, warn_duplicates: bool) -> Self {
+        self.warn_duplicates = Some(warn_duplicates);
+        self
+    }
+
+    /// Whether `warn-duplicates` ignores case (`duplicates-ignore-case`).
+    pub fn duplicates_ignore_case(mut self, duplicates_ignore_case: bool) -> Self {
+        self.duplicates_ignore_case = Some(duplicates_ignore_case);
+        self
+    }
+
+    /// Smallest table checked by `warn-duplicates` (`duplicates-min-rows`).
+    pub fn duplicates_min_rows(mut self, duplicates_min_rows: usize) -> Self {
+        self.duplicates_min_rows = Some(duplicates_min_rows);
+        self
+    }
+
+    /// Adds a die with named faces, used with a `d:name` header
+    /// (`custom-dice`). Replaces a die with the same name from `book.toml`.
+    pub fn custom_die(mut self, name: &str, faces: Vec<String>) -> Self {
+        self.custom_dice.insert(name.into(), faces);
+        self
+    }
+
+    /// Adds a marker like "dd" that tables can have instead of "d" to get
+    /// their die chosen another way (`markers`).
+    pub fn marker(mut self, marker: &str, policy: MarkerPolicy) -> Self {
+        self.markers.insert(marker.into(), policy);
+        self
+    }
+
+    /// Adds a preset of 2d6 bands like `["2-3", "4-6", "7-9", "10-12"]`, used
+    /// with a `2d6!name` header or a `bands=name` directive (`bands`).
+    pub fn bands(mut self, name: &str, bands: Vec<String>) -> Self {
+        self.bands.insert(name.into(), bands);
+        self
+    }
+
+    /// Label of a face that appears several times on a custom die, with
+    /// `{face}` and `{count}` in it (`custom-face-label`).
+    pub fn custom_face_label(mut self, custom_face_label: &str) -> Self {
+        self.custom_face_label = Some(custom_face_label.into());
+        self
+    }
+
+    /// Header corner of a `dxd` table, with `{row}` and `{column}` in it
+    /// (`matrix-corner`).
+    pub fn matrix_corner(mut self, matrix_corner: &str) -> Self {
+        self.matrix_corner = Some(matrix_corner.into());
+        self
+    }
+
+    /// Writes a JSON file listing the converted tables, relative to the book
+    /// root (`manifest`).
+    pub fn manifest(mut self, manifest: &str) -> Self {
+        self.manifest = Some(manifest.into());
+        self
+    }
+
+    /// Keeps the converted chapters in this directory, relative to the book
+    /// root, so `mdbook serve` only converts the ones that changed (`cache`).
+    pub fn cache(mut self, cache: &str) -> Self {
+        self.cache = Some(cache.into());
+        self
+    }
+
+    /// Which dice a d66 is rolled with (`d66-dice`).
+    pub fn d66_dice(mut self, d66_dice: D66Dice) -> Self {
+        self.d66_dice = Some(d66_dice);
+        self
+    }
+
+    /// Added to the header of a d66 rolled with d10s (`d66-d10-suffix`).
+    pub fn d66_d10_suffix(mut self, d66_d10_suffix: &str) -> Self {
+        self.d66_d10_suffix = Some(d66_d10_suffix.into());
+        self
+    }
+
+    /// Renderers the book is converted for, the others get it unchanged
+    /// (`convert-in`).
+    pub fn convert_in(mut self, renderers: Vec<String>) -> Self {
+        self.convert_in = Some(renderers);
+        self
+    }
+
+    /// Whether the sort directive ignores case (`sort-ignore-case`).
+    pub fn sort_ignore_case(mut self, sort_ignore_case: bool) -> Self {
+        self.sort_ignore_case = Some(sort_ignore_case);
+        self
+    }
+
+    /// Whether rows with the same result become one row with all their
+    /// faces (`merge-duplicates`).
+    pub fn merge_duplicates(mut self, merge_duplicates: MergeDuplicates) -> Self {
+        self.merge_duplicates = Some(merge_duplicates);
+        self
+    }
+
+    /// How dice expressions in the text outside tables are formatted
+    /// (`style-dice-in-prose`).
+    pub fn style_dice_in_prose(mut self, style_dice_in_prose: ProseDice) -> Self {
+        self.style_dice_in_prose = Some(style_dice_in_prose);
+        self
+    }
+
+    /// Whether raw HTML tables with a "d" header are converted too
+    /// (`html-table-support`).
+    pub fn html_table_support(mut self, html_table_support: bool) -> Self {
+        self.html_table_support = Some(html_table_support);
+        self
+    }
+
+    /// Die rolled for every table whose number of rows divides its faces,
+    /// e.g. a d20 for a 10 row table (`default-die`).
+    pub fn default_die(mut self, default_die: usize) -> Self {
+        self.default_die = Some(default_die);
+        self
+    }
+
+    /// Whether tables with an empty header instead of "d" above an empty
+    /// first column are converted too (`assume-empty-header`).
+    pub fn assume_empty_header(mut self, assume_empty_header: bool) -> Self {
+        self.assume_empty_header = Some(assume_empty_header);
+        self
+    }
+
+    /// Smallest table converted by `assume-empty-header`
+    /// (`empty-header-min-rows`).
+    pub fn empty_header_min_rows(mut self, empty_header_min_rows: usize) -> Self {
+        self.empty_header_min_rows = Some(empty_header_min_rows);
+        self
+    }
+
+    /// Numbers of rows rolled with the smallest standard die with a multiple
+    /// of their faces, e.g. a d6 for 3 rows (`double-small-tables`).
+    pub fn double_small_tables(mut self, counts: Vec<usize>) -> Self {
+        self.double_small_tables = Some(counts);
+        self
+    }
+
+    /// How the dice in headers and prose are written (`die-markup`).
+    pub fn die_markup(mut self, die_markup: DieMarkup) -> Self {
+        self.die_markup = Some(die_markup);
+        self
+    }
+
+    /// Whether converted tables in chapters with Windows line endings get
+    /// them too (`preserve-line-endings`).
+    pub fn preserve_line_endings(mut self, preserve_line_endings: bool) -> Self {
+        self.preserve_line_endings = Some(preserve_line_endings);
+        self
+    }
+
+    /// What splits the entries of `rolltable` code blocks into columns
+    /// (`rolltable-delimiter`).
+    pub fn rolltable_delimiter(mut self, rolltable_delimiter: &str) -> Self {
+        self.rolltable_delimiter = Some(rolltable_delimiter.into());
+        self
+    }
+
+    /// Title of a chapter added at the end of the book with the tables
+    /// tagged with a `screen` directive (`screen-chapter`).
+    pub fn screen_chapter(mut self, screen_chapter: &str) -> Self {
+        self.screen_chapter = Some(screen_chapter.into());
+        self
+    }
+
+    /// Whether converted tables with one result column are written as a
+    /// single column with the faces in front of the results
+    /// (`single-column-output`).
+    pub fn single_column_output(mut self, single_column_output: bool) -> Self {
+        self.single_column_output = Some(single_column_output);
+        self
+    }
+
+    /// How the faces are written in front of the results with
+    /// `single-column-output`, `{faces}` being replaced by them
+    /// (`single-column-label`).
+    pub fn single_column_label(mut self, single_column_label: &str) -> Self {
+        self.single_column_label = Some(single_column_label.into());
+        self
+    }
+
+    /// Whether chapters with roll tables start with a comment with their
+    /// dice and faces as JSON (`emit-metadata`).
+    pub fn emit_metadata(mut self, emit_metadata: bool) -> Self {
+        self.emit_metadata = Some(emit_metadata);
+        self
+    }
+
+    /// For which renderers chapters with converted tables end with a comment
+    /// with their content as it was before (`debug-keep-original`).
+    pub fn debug_keep_original(mut self, debug_keep_original: KeepOriginal) -> Self {
+        self.debug_keep_original = Some(debug_keep_original);
+        self
+    }
+
+    /// Whether a bold line with a die right above a `d` table gives it that
+    /// die and its name (`caption-detection`).
+    pub fn caption_detection(mut self, caption_detection: bool) -> Self {
+        self.caption_detection = Some(caption_detection);
+        self
+    }
+
+    /// Pairs of start and end comments, like `<!-- tabs:start -->` and
+    /// `<!-- tabs:end -->`, whose tables are left as they are
+    /// (`protected-regions`).
+    pub fn protected_regions(mut self, protected_regions: Vec<(String, String)>) -> Self {
+        self.protected_regions = Some(protected_regions);
+        self
+    }
+
+    /// Whether the tables in `protected-regions` are converted anyway
+    /// (`convert-in-protected`).
+    pub fn convert_in_protected(mut self, convert_in_protected: bool) -> Self {
+        self.convert_in_protected = Some(convert_in_protected);
+        self
+    }
+
+    /// Sends the warnings and stats to `f` instead of stderr, e.g. to tell
+    /// apart the ones of books converted at the same time. `f` gets each
+    /// block of lines at once, every line ending in a newline.
+    pub fn report_to(mut self, f: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.report = Report::new(f);
+        self
+    }
+
+    /// Sends every warning to `sink` as a [`Diagnostic`] when it is found,
+    /// instead of printing them. `strict` still fails the build.
+    pub fn with_sink(mut self, sink: impl DiagnosticSink + 'static) -> Self {
+        self.sink = Sink::new(sink);
+        self
+    }
+
+    /// Calls `f` with the path of every chapter that is converted rather
+    /// than taken from the `cache`, e.g. to check that the cache is used.
+    pub fn on_convert(mut self, f: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.on_convert = ConvertHook::new(f);
+        self
+    }
+
+    /// Creates the preprocessor.
+    pub fn build(self) -> RollTables {
+        RollTables { overrides: self }
+    }
+}
+
+#[cfg(feature = "mdbook")]
+impl Preprocessor for RollTables {
+    fn name(&self) -> &str {
+        "rolltables"
+    }
+
+    /// The output is plain markdown, so every renderer is supported. Options
+    /// that only work in HTML are turned off for the others.
+    fn supports_renderer(&self, _renderer: &str) -> bool {
+        true
+    }
+
+    fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
+        let mut config = Config::new(ctx.config.get_preprocessor(self.name()), &self.overrides)?;
+        config.restrict_to(&ctx.renderer);
+        if config.disabled || !config.converts_in(&ctx.renderer) {
+            return Ok(book);
+        }
+
+        let cache = config.cache.as_ref().map(|cache| ctx.root.join(cache));
+        let tables = self.process_book(&mut book, &config, &mut |_, _| {}, cache.as_deref())?;
+        if let Some(manifest) = &config.manifest {
+            let src = ctx.root.join(&ctx.config.book.src);
+            write_manifest(&ctx.root.join(manifest), &src, &tables)?;
+        }
+        Ok(book)
+    }
+}
+
+/// Like [`Book::for_each_mut`] but stops at the first error.
+fn for_each_chapter(book: &mut Book, mut f: impl FnMut(&mut Chapter) -> Result<()>) -> Result<()> {
+    let mut result = Ok(());
+    book.for_each_mut(|item| match item {
+        BookItem::Chapter(chapter) if result.is_ok() => result = f(chapter),
+        _ => {}
+    });
+    result
+}
+
+/// What is collected while going through the chapters.
+#[derive(Default)]
+struct BookState {
+    defined: HashMap<String, DefinedTable>,
+    tables: Vec<TableInfo>,
+    /// Tables tagged with a `screen` directive, in the order of the book.
+    screen: Vec<ScreenTable>,
+    diagnostics: Diagnostics,
+}
+
+/// What [`RollTables::transform_table`] did with a table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableOutcome {
+    /// It isn't a roll table, or couldn't be converted.
+    Unchanged,
+    /// The marker was escaped and is now a plain "d".
+    Unescaped,
+    /// The first column has the faces of `die` now.
+    Converted {
+        /// The header of the die column, e.g. `d66`.
+        die: String,
+        /// How the die was picked.
+        choice: DieChoice,
+        /// How many faces of the die every row has.
+        weights: Vec<usize>,
+        /// How the table is rolled, `None` for custom dice and `dxd` tables.
+        formula: Option<Formula>,
+    },
+}
+
+impl TableOutcome {
+    /// Sets the formula of a converted table.
+    fn with_formula(self, formula: Formula) -> Self {
+        match self {
+            Self::Converted {
+                die,
+                choice,
+                weights,
+                ..
+            } => Self::Converted {
+                die,
+                choice,
+                weights,
+                formula: Some(formula),
+            },
+            outcome => outcome,
+        }
+    }
+
+    /// Sets how the die of a converted table was picked.
+    fn with_choice(self, choice: DieChoice) -> Self {
+        match self {
+            Self::Converted {
+                die,
+                weights,
+                formula,
+                ..
+            } => Self::Converted {
+                die,
+                choice,
+                weights,
+                formula,
+            },
+            outcome => outcome,
+        }
+    }
+}
+
+/// Called with every table by [`RollTables::process_with`].
+type TableHook<'h> = dyn FnMut(&mut MarkdownTable, &TableOutcome) + 'h;
+
+/// A table saved with a `define` directive.
+struct DefinedTable {
+    markdown: String,
+    location: String,
+}
+
+impl RollTables {
+    /// Returns what is known about every converted table. Chapters are
+    /// taken from the `cache` directory `cache` if it is given.
+    fn process_book(
+        &self,
+        book: &mut Book,
+        config: &Config,
+        hook: &mut TableHook,
+        cache: Option<&Path>,
+    ) -> Result<Vec<TableInfo>> {
+        // Tables are defined anywhere in the book, so all of them are
+        // converted before any `use` directive is replaced.
+        let mut state = BookState {
+            diagnostics: Diagnostics::new(&config.sink),
+            ..BookState::default()
+        };
+        let cache = cache.map(|dir| Cache::new(dir, config));
+        for_each_chapter(book, |chapter| {
+            let cached = cache.as_ref().and_then(|cache| cache.get(chapter));
+            if let Some((converted, tables)) = cached {
+                chapter.content = converted;
+                state.tables.extend(tables);
+                return Ok(());
+            }
+            config.on_convert.call(&location(chapter));
+            let cache = match &cache {
+                Some(cache) => cache,
+                None => return self.handle_chapter(chapter, config, &mut state, hook),
+            };
+
+            let content = chapter.content.clone();
+            let first_table = state.tables.len();
+            let (defined, screen) = (state.defined.len(), state.screen.len());
+            let warnings = state.diagnostics.count();
+            self.handle_chapter(chapter, config, &mut state, hook)?;
+            // Chapters that define tables, put them on the screen or warn
+            // about anything change more than their own content, so they are
+            // converted every time.
+            if state.defined.len() == defined
+                && state.screen.len() == screen
+                && state.diagnostics.count() == warnings
+            {
+                cache.put(content, chapter, &state.tables[first_table..])?;
+            }
+            Ok(())
+        })?;
+        for_each_chapter(book, |chapter| {
+            self.use_tables(chapter, config, &state.defined)
+        })?;
+
+        if let Some(title) = &config.screen_chapter {
+            if !state.screen.is_empty() {
+                book.push_item(screen_chapter(title, &state.screen));
+            }
+        }
+
+        let mut named: HashMap<&str, &str> = HashMap::new();
+        for info in &state.tables {
+            if let Some(previous) = named.insert(&info.table.name, &info.location) {
+                Err(anyhow!(
+                    "Roll table name \"{}\" is used both in {} and in {}",
+                    info.table.name,
+                    previous,
+                    info.location
+                ))?;
+            }
+        }
+
+        state.diagnostics.finish(config)?;
+
+        if config.stats {
+            print_stats(&state.tables, config.detailed_stats, &config.report);
+        }
+
+        Ok(state.tables)
+    }
+
+    /// Only the source of converted tables, and of dice in prose with
+    /// `style-dice-in-prose`, is rewritten, everything else is copied byte
+    /// for byte so it renders exactly as if the preprocessor didn't run.
+    fn handle_chapter(
+        &self,
+        chapter: &mut Chapter,
+        config: &Config,
+        state: &mut BookState,
+        hook: &mut TableHook,
+    ) -> Result<()> {
+        let (bom, whole) = split_bom(&chapter.content);
+        // The comment of `debug-keep-original` from converting the chapter
+        // before is copied as it is, the tables in it aren't converted.
+        let (source, kept_original) = split_kept_original(whole);
+        let separated = separate_stacked_tables(source, config);
+        let source: &str = &separated;
+        let mut buf = String::with_capacity(chapter.content.len());
+        buf.push_str(bom);
+        let mut copied = 0;
+        let mut pending = PendingDirectives::default();
+        let mut html_table_name = None;
+        let mut chapter_options: Option<Vec<ChapterOption>> = None;
+        let mut chapter_config = Cow::Borrowed(config);
+        let mut chapter_screen = false;
+        let mut chapter_add_die_column = false;
+        let mut chapter_bands = None;
+        let mut html_table_screen = false;
+        let mut heading = String::new();
+        let mut in_heading = false;
+        let mut in_code_block = false;
+        let mut in_image = false;
+        let mut in_strong = false;
+        let mut in_html_code = false;
+        let mut paragraph: Option<Vec<Event>> = None;
+        let mut caption = None;
+        let mut html_table = None;
+        let mut tables_found = false;
+        let mut table_in_html = false;
+        let mut previous_html: Option<&str> = None;
+        let mut in_html_pre = false;
+        // How many regions of each pair of `protected-regions` are open.
+        let mut protected = vec![0usize; config.protected_regions.len()];
+        // Every table, converted or not, for `Diagnostic::table`.
+        let mut table_index = 0;
+        state.diagnostics.chapter(chapter);
+        let mut names = TableNames::new(chapter);
+        // The blank lines between stacked tables count as rewriting them.
+        let mut rewritten = usize::from(matches!(separated, Cow::Owned(_)));
+        let first_table = state.tables.len();
+        // Where the last line of HTML ends, e.g. of a directive.
+        let mut html_end = None;
+
+        let mut events = Parser::new_ext(source, parser_options()).into_offset_iter();
+
+        while let Some((ev, range)) = events.next() {
+            // Options of a `chapter` directive apply from the next event on.
+            for option in chapter_options.take().unwrap_or_default() {
+                match option {
+                    ChapterOption::Marker(policy) => {
+                        chapter_config.to_mut().markers.insert("d".into(), policy);
+                    }
+                    ChapterOption::Bands(name) => chapter_bands = Some(name),
+                    ChapterOption::Screen => chapter_screen = true,
+                    ChapterOption::AddDieColumn => chapter_add_die_column = true,
+                }
+            }
+            let config: &Config = &chapter_config;
+
+            match &ev {
+                Event::Start(Tag::Heading(_)) => {
+                    in_heading = true;
+                    heading.clear();
+                }
+                Event::End(Tag::Heading(_)) => in_heading = false,
+                Event::Text(text) | Event::Code(text) if in_heading => heading.push_str(text),
+                Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+                Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+                Event::Start(Tag::Image(..)) => in_image = true,
+                Event::End(Tag::Image(..)) => in_image = false,
+                Event::Start(Tag::Strong) => in_strong = true,
+                Event::End(Tag::Strong) => in_strong = false,
+                Event::Html(html) if html.starts_with("<code") => in_html_code = true,
+                Event::Html(html) if html.starts_with("</code") => in_html_code = false,
+                _ => {}
+            }
+
+            // Another preprocessor running first may have put the tables in
+            // HTML, where they aren't read, one line per event.
+            match &ev {
+                Event::Start(Tag::Table(_)) => tables_found = true,
+                Event::Html(_) => {
+                    let line = &source[range.clone()];
+                    if line.trim_start().starts_with("<pre") {
+                        in_html_pre = true;
+                    }
+                    let head = previous_html.and_then(|head| table_head(head, line));
+                    if head.is_some_and(|marker| looks_like_marker(&marker, config)) && !in_html_pre
+                    {
+                        table_in_html = true;
+                    }
+                    if line.contains("</pre") {
+                        in_html_pre = false;
+                    }
+                    previous_html = Some(line);
+                }
+                _ => previous_html = None,
+            }
+
+            // Regions nest, and an end comment without a start is ignored.
+            if let Event::Html(html) = &ev {
+                let html = html.trim();
+                for (open, (start, end)) in protected.iter_mut().zip(&config.protected_regions) {
+                    if *open > 0 && html == end {
+                        *open -= 1;
+                    } else if html == start {
+                        *open += 1;
+                    } else if html == end {
+                        state.diagnostics.warn(
+                            Warning::UnmatchedProtectedEnd { end: end.clone() },
+                            &location(chapter),
+                        );
+                    }
+                }
+            }
+            let in_protected =
+                !config.convert_in_protected && protected.iter().any(|&open| open > 0);
+
+            if config.caption_detection {
+                match &ev {
+                    Event::Start(Tag::Paragraph) => paragraph = Some(vec![]),
+                    Event::End(Tag::Paragraph) => {
+                        caption = paragraph
+                            .take()
+                            .and_then(|events| caption_text(&events))
+                            .map(|text| (text, range.end));
+                    }
+                    ev => {
+                        if let Some(events) = &mut paragraph {
+                            events.push(ev.clone());
+                        }
+                    }
+                }
+            }
+
+            // A roll table with a delimiter row of the wrong length is a
+            // paragraph instead, which would go unnoticed.
+            if let Event::Start(Tag::Paragraph) = &ev {
+                if let Some((marker, head, delimiters)) = mismatched_head(&source[range.clone()]) {
+                    if looks_like_marker(&marker, config) {
+                        state.diagnostics.warn(
+                            Warning::MismatchedColumns { head, delimiters },
+                            &location(chapter),
+                        );
+                    }
+                }
+            }
+
+            // Already bold dice are left alone so converting twice doesn't
+            // nest the asterisks, image alt text is rendered as plain text.
+            let mark = match config.style_dice_in_prose {
+                ProseDice::Unchanged => None,
+                ProseDice::Bold if in_strong => None,
+                ProseDice::Bold => Some("**"),
+                ProseDice::Code => Some("`"),
+            };
+            if let (Event::Text(_), Some(mark)) = (&ev, mark) {
+                if !in_code_block && !in_image && !in_html_code {
+                    for dice in find_dice(&source[range.clone()]) {
+                        let dice = range.start + dice.start..range.start + dice.end;
+                        buf.push_str(&source[copied..dice.start]);
+                        let die = inline_to_markdown(&die_events(
+                            &source[dice.clone()],
+                            config.die_markup,
+                        ));
+                        // HTML would be shown as written in a code span.
+                        if mark == "`" && config.die_markup == DieMarkup::Span {
+                            buf.push_str(&format!("<code>{}</code>", die));
+                        } else {
+                            buf.push_str(mark);
+                            buf.push_str(&die);
+                            buf.push_str(mark);
+                        }
+                        copied = dice.end;
+                        rewritten += 1;
+                    }
+                }
+            }
+
+            if config.html_table_support {
+                let html = &source[range.clone()];
+                if matches!(ev, Event::Html(_))
+                    && html_table.is_none()
+                    && !in_protected
+                    && html.trim_start().to_ascii_lowercase().starts_with("<table")
+                {
+                    html_table = Some(range.start);
+                    html_table_name = pending.name.take();
+                    html_table_screen = std::mem::take(&mut pending.screen) || chapter_screen;
+                }
+                match (&ev, html_table) {
+                    (Event::Html(_), Some(start)) => {
+                        if let Some(end) = html.to_ascii_lowercase().find("</table>") {
+                            let end = range.start + end + "</table>".len();
+                            html_table = None;
+                            state.diagnostics.table(Some(table_index));
+                            table_index += 1;
+                            let converted = convert_html_table(
+                                &source[start..end],
+                                &location(chapter),
+                                config,
+                                &mut state.diagnostics,
+                            );
+                            state.diagnostics.table(None);
+                            if let Some((converted, table, weights, choice)) = converted {
+                                let name = pick_name(html_table_name.take(), names.next(&heading));
+                                if std::mem::take(&mut html_table_screen) {
+                                    state.screen.push(ScreenTable {
+                                        name: name.clone(),
+                                        description: choice.describe(&table.die, &weights),
+                                        markdown: converted.clone(),
+                                    });
+                                }
+                                state.tables.push(TableInfo {
+                                    table: RollTable { name, ..table },
+                                    location: location(chapter),
+                                    source_path: chapter.source_path.clone(),
+                                    range: bom.len() + start..bom.len() + end,
+                                    weights,
+                                    choice,
+                                });
+                                buf.push_str(&source[copied..start]);
+                                buf.push_str(&converted);
+                                copied = end;
+                                rewritten += 1;
+                            }
+                        }
+                    }
+                    (_, Some(_)) => {
+                        html_table = None;
+                        state.diagnostics.table(Some(table_index));
+                        table_index += 1;
+                        state.diagnostics.warn(
+                            Warning::MalformedHtmlTable {
+                                reason: "no </table> before the end of the HTML block",
+                            },
+                            &location(chapter),
+                        );
+                        state.diagnostics.table(None);
+                    }
+                    _ => {}
+                }
+            }
+
+            // A `rolltable` code block becomes a table, so it's handled as
+            // one from here on.
+            let table = match &ev {
+                Event::Start(Tag::Table(alignment)) => {
+                    Some(MarkdownTable::new(alignment.clone(), &mut events, source))
+                }
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))
+                    if fenced::is_rolltable(info) =>
+                {
+                    let mut code = String::new();
+                    for (ev, _) in &mut events {
+                        match ev {
+                            Event::Text(text) => code.push_str(&text),
+                            Event::End(Tag::CodeBlock(_)) => break,
+                            _ => {}
+                        }
+                    }
+                    in_code_block = false;
+                    let table = fenced::parse_block(info, &code, &config.rolltable_delimiter)
+                        .map_err(|e| {
+                            e.context(format!("Invalid rolltable block in {}", location(chapter)))
+                        })?;
+                    Some(table)
+                }
+                _ => None,
+            };
+            let fenced = table.is_some() && !matches!(ev, Event::Start(Tag::Table(_)));
+            if table.is_some() {
+                state.diagnostics.table(Some(table_index));
+                table_index += 1;
+            }
+            if let Event::Html(_) = ev {
+                html_end = Some(range.end);
+            }
+
+            match (ev, table) {
+                (Event::Html(html), _) => match Directive::parse(&html) {
+                    Ok(Some(Directive::Chapter(options))) => chapter_options = Some(options),
+                    Ok(Some(directive)) => pending.push(directive),
+                    Ok(None) => {}
+                    Err(e) => Err(e.context(format!(
+                        "Invalid rolltables directive in {}",
+                        location(chapter)
+                    )))?,
+                },
+                (_, Some(mut table)) => {
+                    let original = &source[range.clone()];
+                    let mut directives = std::mem::take(&mut pending);
+                    let mut explicit_name = directives.name.take();
+
+                    let added_die_column = (directives.add_die_column || chapter_add_die_column)
+                        && !has_die_column(&table, config);
+                    if added_die_column {
+                        table.insert_die_column(config.generated_column_alignment.into());
+                    }
+
+                    // A bold line right above the table, like "**Encounters —
+                    // roll d8**", gives it a die and a name.
+                    let caption = caption
+                        .take()
+                        .filter(|(_, end)| source[*end..range.start].trim().is_empty())
+                        .and_then(|(text, _)| {
+                            let die = find_dice(&text)
+                                .into_iter()
+                                .map(|dice| text[dice].to_string())
+                                .find(|die| ExplicitDie::parse(die).is_some())?;
+                            Some((text, die))
+                        });
+                    let marker_cell = table.head()[0].clone();
+                    if let Some((text, die)) = caption {
+                        if plain_text(&marker_cell) == "d" {
+                            replace_plain_text(&mut table.head_mut()[0], die);
+                            explicit_name = explicit_name.or(Some(text));
+                        }
+                    }
+
+                    directives.skip_rows(&mut table, &location(chapter))?;
+                    let unshuffled =
+                        directives.order_rows(&mut table, config, &location(chapter))?;
+                    directives.check_wrap_columns(&table, &location(chapter))?;
+
+                    // An annotation after the marker, like `d (hours)`, goes
+                    // back after the die.
+                    let annotation =
+                        split_annotation(&table.head()[0]).map(|(marker, annotation)| {
+                            table.head_mut()[0] = marker;
+                            annotation
+                        });
+
+                    let left_alone = directives.skip || in_protected;
+                    let outcome = if left_alone {
+                        TableOutcome::Unchanged
+                    } else {
+                        self.transform(
+                            &mut table,
+                            directives.bands.take().or_else(|| chapter_bands.clone()),
+                            directives.reserve_top,
+                            &location(chapter),
+                            config,
+                            &mut state.diagnostics,
+                        )?
+                    };
+                    if matches!(outcome, TableOutcome::Unchanged) {
+                        if let Some(unshuffled) = unshuffled {
+                            table = unshuffled;
+                        }
+                        table.head_mut()[0] = marker_cell;
+                        if added_die_column {
+                            table.remove_die_column();
+                        }
+                    } else if let Some(annotation) = annotation {
+                        table.head_mut()[0].extend(annotation);
+                    }
+                    let wrapped = !left_alone && directives.wrap_columns(&mut table);
+                    let before = table.clone();
+                    hook(&mut table, &outcome);
+                    let hooked = table != before;
+                    let single_column = config.single_column_output
+                        && matches!(outcome, TableOutcome::Converted { .. })
+                        && table.head().len() == 2;
+                    let write = |indent: &str| {
+                        if single_column {
+                            single_column_markdown(&table, indent, config)
+                        } else {
+                            table.to_markdown(indent, config.pretty_tables)
+                        }
+                    };
+
+                    let on_screen = directives.screen || chapter_screen;
+                    if let TableOutcome::Converted {
+                        die,
+                        choice,
+                        weights,
+                        formula,
+                    } = &outcome
+                    {
+                        let name = pick_name(explicit_name, names.next(&heading));
+                        if on_screen {
+                            state.screen.push(ScreenTable {
+                                name: name.clone(),
+                                description: choice.describe(die, weights),
+                                markdown: write(""),
+                            });
+                        }
+                        state.tables.push(TableInfo {
+                            table: RollTable::from_markdown(
+                                name,
+                                die.clone(),
+                                *formula,
+                                &table,
+                                config,
+                            ),
+                            location: location(chapter),
+                            source_path: chapter.source_path.clone(),
+                            range: bom.len() + range.start..bom.len() + range.end,
+                            weights: weights.clone(),
+                            choice: *choice,
+                        });
+                    }
+
+                    // A skipped `rolltable` block stays a code block.
+                    if (directives.sorts() || wrapped || hooked) && !fenced
+                        || !matches!(outcome, TableOutcome::Unchanged)
+                    {
+                        let newline = line_ending(original, config);
+                        let line_start = source[..range.start].rfind('\n').map_or(0, |i| i + 1);
+                        let prefix = &source[line_start..range.start];
+                        buf.push_str(&source[copied..line_start]);
+                        // Right below a paragraph or a table the roll table
+                        // a block becomes would be part of it, and the same
+                        // goes for a paragraph right below it. HTML, e.g. a
+                        // directive, ends before it.
+                        let separate = |line: Option<&str>| {
+                            fenced && line.is_some_and(|line| !is_blank_line(line))
+                        };
+                        let after_html = html_end == Some(line_start);
+                        if !after_html && separate(source[..line_start].lines().next_back()) {
+                            buf.push_str(prefix.trim_end());
+                            buf.push_str(newline);
+                        }
+                        buf.push_str(prefix);
+                        buf.push_str(&write(&indent_of(original)).replace('\n', newline));
+                        if original.ends_with('\n') {
+                            buf.push_str(newline);
+                        }
+                        let rest = source[range.end..].split_once('\n');
+                        if separate(rest.and_then(|(_, rest)| rest.lines().next())) {
+                            buf.push_str(prefix.trim_end());
+                            buf.push_str(newline);
+                        }
+                        copied = range.end;
+                        rewritten += 1;
+                    }
+
+                    if let Some(name) = directives.define {
+                        let table = DefinedTable {
+                            markdown: write(""),
+                            location: location(chapter),
+                        };
+                        if let Some(previous) = state.defined.insert(name.clone(), table) {
+                            Err(anyhow!(
+                                "Roll table \"{}\" is defined both in {} and in {}",
+                                name,
+                                previous.location,
+                                location(chapter)
+                            ))?;
+                        }
+                    }
+                }
+                _ => pending.check_followed(&location(chapter))?,
+            }
+            state.diagnostics.table(None);
+        }
+
+        if html_table.is_some() {
+            state.diagnostics.table(Some(table_index));
+            state.diagnostics.warn(
+                Warning::MalformedHtmlTable {
+                    reason: "no </table> before the end of the chapter",
+                },
+                &location(chapter),
+            );
+            state.diagnostics.table(None);
+        }
+        if table_in_html && !tables_found {
+            state
+                .diagnostics
+                .warn(Warning::TableInHtml, &location(chapter));
+        }
+        for (open, (start, _)) in protected.iter().zip(&config.protected_regions) {
+            if *open > 0 {
+                state.diagnostics.warn(
+                    Warning::UnclosedProtectedRegion {
+                        start: start.clone(),
+                    },
+                    &location(chapter),
+                );
+            }
+        }
+
+        buf.push_str(&source[copied..]);
+
+        let tables = &state.tables[first_table..];
+        if config.emit_metadata && !tables.is_empty() {
+            let comment = metadata_comment(tables)? + line_ending(source, config);
+            buf.insert_str(bom.len(), &comment);
+        }
+        if let Some(kept) = kept_original {
+            buf.push_str(kept);
+        } else if config.debug_keep_original != KeepOriginal::Never && !tables.is_empty() {
+            // A comment can't contain "--", which also keeps the directives
+            // in it from being read.
+            let newline = line_ending(source, config);
+            // One blank line before it, whether the chapter ends with a line
+            // break or not.
+            if !buf.ends_with('\n') {
+                buf.push_str(newline);
+            }
+            buf.push_str(&format!(
+                "{0}{1}{0}{2}{0}-->{0}",
+                newline,
+                KEPT_ORIGINAL,
+                whole.replace("--", "- -")
+            ));
+        }
+        keep_final_newline(whole, &mut buf, config);
+
+        if config.assert_stable {
+            self.assert_stable(chapter, &buf, rewritten, config)?;
+        }
+
+        chapter.content = buf;
+        Ok(())
+    }
+
+    /// Checks for `assert-stable` that a chapter without rewritten tables
+    /// comes out byte-identical and that converting the output again
+    /// doesn't change it.
+    fn assert_stable(
+        &self,
+        chapter: &Chapter,
+        output: &str,
+        rewritten: usize,
+        config: &Config,
+    ) -> Result<()> {
+        if rewritten == 0 && output != chapter.content {
+            Err(anyhow!(
+                "assert-stable: {} has no converted tables but changed:\n{}",
+                location(chapter),
+                context_diff(&chapter.content, output)
+            ))?;
+        }
+
+        let mut again = chapter.clone();
+        again.content = output.to_string();
+        let config = Config {
+            assert_stable: false,
+            ..config.clone()
+        };
+        self.handle_chapter(
+            &mut again,
+            &config,
+            &mut BookState::default(),
+            &mut |_, _| {},
+        )?;
+
+        if again.content != output {
+            Err(anyhow!(
+                "assert-stable: converting {} again changes it:\n{}",
+                location(chapter),
+                context_diff(output, &again.content)
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Replaces `use` directives with the tables they name.
+    fn use_tables(
+        &self,
+        chapter: &mut Chapter,
+        config: &Config,
+        defined: &HashMap<String, DefinedTable>,
+    ) -> Result<()> {
+        let (bom, source) = split_bom(&chapter.content);
+        let mut buf = String::with_capacity(chapter.content.len());
+        buf.push_str(bom);
+        let mut copied = 0;
+
+        for (ev, range) in Parser::new_ext(source, parser_options()).into_offset_iter() {
+            if let Event::Html(html) = ev {
+                if let Ok(Some(Directive::Use(name))) = Directive::parse(&html) {
+                    let table = defined.get(&name).ok_or_else(|| {
+                        anyhow!(
+                            "Roll table \"{}\" used in {} is never defined",
+                            name,
+                            location(chapter)
+                        )
+                    })?;
+
+                    let line_start = source[..range.start].rfind('\n').map_or(0, |i| i + 1);
+                    let indent = &source[line_start..range.start];
+                    if !indent.chars().all(|c| c.is_whitespace() || c == '>') {
+                        Err(anyhow!(
+                            "The use directive for \"{}\" in {} must be on its own line",
+                            name,
+                            location(chapter)
+                        ))?;
+                    }
+
+                    // The table has to be its own block, not a continuation
+                    // of the surrounding ones.
+                    let line_end = source[range.start..]
+                        .find('\n')
+                        .map_or(source.len(), |i| range.start + i + 1);
+                    let newline = line_ending(&source[range.start..line_end], config);
+                    buf.push_str(&source[copied..line_start]);
+                    let blank_line = buf.ends_with("\n\n") || buf.ends_with("\n\r\n");
+                    if buf.len() > bom.len() && !blank_line {
+                        buf.push_str(newline);
+                    }
+                    buf.push_str(indent);
+                    buf.push_str(
+                        &table
+                            .markdown
+                            .replace('\n', &format!("{}{}", newline, indent)),
+                    );
+                    buf.push_str(newline);
+                    let rest = &source[range.end..];
+                    if !rest.is_empty() && !rest.starts_with('\n') && !rest.starts_with("\r\n") {
+                        buf.push_str(newline);
+                    }
+                    copied = range.end;
+                }
+            }
+        }
+
+        buf.push_str(&source[copied..]);
+        keep_final_newline(source, &mut buf, config);
+        chapter.content = buf;
+        Ok(())
+    }
+
+    /// Converts a table with whichever of `reserve-top`, percentages,
+    /// `bands` and the marker in its header applies.
+    fn transform(
+        &self,
+        table: &mut MarkdownTable,
+        bands: Option<String>,
+        reserve_top: Option<usize>,
+        location: &str,
+        config: &Config,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<TableOutcome> {
+        // Every way of converting needs all the rows.
+        if !table.complete {
+            diagnostics.warn(Warning::Truncated, location);
+            return Ok(TableOutcome::Unchanged);
+        }
+
+        // Parsed, the "d" of an escaped marker written before is a marker
+        // again, only its source tells them apart.
+        if table.cell_to_markdown(&table.head()[0]).trim() == UNESCAPED_MARKER {
+            return Ok(TableOutcome::Unchanged);
+        }
+
+        // The directive is left in front of the converted table, which has a
+        // "2d6" header then.
+        let marker = plain_text(&table.head()[0]);
+        let preset = match marker.strip_prefix("2d6!") {
+            Some(name) => Some(name.trim().to_string()),
+            None => bands.filter(|_| marker == "d"),
+        };
+        let outcome = if let Some(reserved) = reserve_top {
+            convert_reserved(table, reserved, location, config, diagnostics)?
+        } else if has_percentages(table) {
+            convert_percentages(table, location, config, diagnostics)?
+        } else if let Some(preset) = preset {
+            convert_bands(table, &preset, location, config, diagnostics)?
+        } else {
+            self.convert_table(table, location, config, diagnostics)
+        };
+
+        if let TableOutcome::Converted { .. } = outcome {
+            for row in table.rows_mut() {
+                for cell in row.iter_mut().skip(1) {
+                    number_sub_rolls(cell);
+                }
+            }
+        }
+        Ok(outcome)
+    }
+
+    fn convert_table(
+        &self,
+        table: &mut MarkdownTable,
+        location: &str,
+        config: &Config,
+        diagnostics: &mut Diagnostics,
+    ) -> TableOutcome {
+        // An escaped marker (`d\` or `` `d` ``) shows the table as is, with a
+        // plain "d" in the header. It's written as a character reference so
+        // that converting the output again leaves it alone.
+        if is_escaped_marker(&table.head()[0]) {
+            table.head_mut()[0] = vec![Event::Html(UNESCAPED_MARKER.into())];
+            return TableOutcome::Unescaped;
+        }
+
+        if let Some(name) = plain_text(&table.head()[0]).strip_prefix("d:") {
+            return match custom_die_labels(name.trim(), table, location, config, diagnostics) {
+                Some((head, labels, weights)) => {
+                    label_table(table, head, labels, weights, location, config, diagnostics)
+                        .with_choice(DieChoice::Custom)
+                }
+                None => TableOutcome::Unchanged,
+            };
+        }
+
+        if plain_text(&table.head()[0]) == "dxd" {
+            return convert_matrix(table, location, config, diagnostics);
+        }
 
-/// The struct that implements Preprocessor trait.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct RollTables;
+        let marker = plain_text(&table.head()[0]);
+        let pair = explicit_pair(&marker, &config.head_separator);
+        if let Some(die) = ExplicitDie::parse(&marker).filter(|_| pair.is_none()) {
+            return convert_explicit(table, &marker, die, location, config, diagnostics);
+        }
 
-impl Preprocessor for RollTables {
-    fn name(&self) -> &str {
-        "rolltables"
-    }
+        // A head without rows has nothing to roll for.
+        if table.rows().is_empty() {
+            return TableOutcome::Unchanged;
+        }
 
-    fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
-        let cfg = ctx.config.get_preprocessor(self.name()).unwrap();
+        // The last row may have `*` instead of an empty cell, taking all the
+        // faces the other rows leave.
+        let overflow =
+            table.rows().len() > 1 && plain_text(&table.rows()[table.rows().len() - 1][0]) == "*";
+        let normal_rows = table.rows().len() - overflow as usize;
 
-        let head_separator = match cfg.get("head-separator") {
-            Some(Value::String(s)) => s.clone(),
-            Some(_) => Err(anyhow!("head-separator must be a string"))?,
-            None => "".into(),
+        // Tables from other tools often leave the header of the die column
+        // empty instead of writing "d".
+        let empty_header = config.assume_empty_header
+            && is_blank(&table.head()[0])
+            && table.rows().len() >= config.empty_header_min_rows;
+        let policy = match config.markers.get(&plain_text(&table.head()[0])) {
+            Some(policy) => *policy,
+            None if empty_header || pair.is_some() => MarkerPolicy::Auto,
+            None => return TableOutcome::Unchanged,
         };
+        if !table.rows()[..normal_rows]
+            .iter()
+            .all(|row| is_blank(&row[0]))
+        {
+            return TableOutcome::Unchanged;
+        }
 
-        let separator = match cfg.get("separator") {
-            Some(Value::String(s)) => s.clone(),
-            Some(_) => Err(anyhow!("separator must be a string"))?,
-            None => ".".into(),
+        let mut count = match table.weights() {
+            Some(weights) => weights.iter().sum(),
+            None => table.rows().len(),
         };
+        if let Some((a, b)) = pair.filter(|&(a, b)| overflow || count != a * b) {
+            diagnostics.warn(
+                Warning::PairRows {
+                    die: format!("d{}{}{}", a, config.head_separator, b),
+                    rows: count,
+                },
+                location,
+            );
+            return TableOutcome::Unchanged;
+        }
+        if overflow {
+            let die = match STANDARD_DICE.iter().find(|&&die| die >= normal_rows) {
+                Some(die) => *die,
+                None => {
+                    diagnostics.warn(Warning::NoDieForOverflow { rows: normal_rows }, location);
+                    return TableOutcome::Unchanged;
+                }
+            };
+
+            if die == normal_rows {
+                diagnostics.warn(Warning::NoFacesForOverflow, location);
+                table.content.pop();
+                count = normal_rows;
+            } else {
+                count = die;
+            }
+        }
 
-        let warn_unusual_dice = match cfg.get("warn-unusual-dice") {
-            Some(Value::Boolean(b)) => *b,
-            Some(_) => Err(anyhow!("warn-unusual-dice must be a bool"))?,
-            None => false,
+        let (choice, head, iter, formula) = match pair {
+            Some((a, b)) => {
+                let choice = DieChoice::Pair(a, b);
+                let (head, iter, formula) =
+                    dice_for_choice(choice, count, config, diagnostics, location);
+                (choice, head, iter, formula)
+            }
+            None => get_dice_iterator(count, policy, config, diagnostics, location),
         };
+        let faces = count;
+        let mut labels: Vec<_> = iter.collect();
+        let mut weights: Vec<usize> = labels.iter().map(Vec::len).collect();
 
-        book.for_each_mut(|item| {
-            if let BookItem::Chapter(chapter) = item {
-                self.handle_chapter(chapter, &head_separator, &separator, warn_unusual_dice)
+        if overflow && count > normal_rows && labels.len() == count {
+            let rest: Vec<String> = labels.drain(normal_rows..).flatten().collect();
+            weights.truncate(normal_rows);
+            weights.push(rest.len());
+            labels.push(match (rest.first(), rest.last()) {
+                (Some(first), Some(last)) if rest.len() > 1 => vec![format!(
+                    "{}{}{}",
+                    first,
+                    config.range_separator.as_deref().unwrap_or("–"),
+                    last
+                )],
+                _ => rest,
+            });
+            count = labels.len();
+        }
+
+        // Every face needs a label before they are shared out between the
+        // rows, `label_table` checks the rows.
+        if labels.len() != count {
+            diagnostics.warn(
+                Warning::LabelCount {
+                    labels: labels.len(),
+                    rows: count,
+                },
+                location,
+            );
+            return TableOutcome::Unchanged;
+        }
+
+        // A weighted row gets the faces of as many rows.
+        let unit = match table.weights() {
+            Some(row_weights) => {
+                let mut faces = labels.into_iter();
+                labels = row_weights
+                    .iter()
+                    .map(|&weight| faces.by_ref().take(weight).flatten().collect())
+                    .collect();
+                weights = labels.iter().map(Vec::len).collect();
+                "face"
             }
-        });
+            None => "row",
+        };
+        warn_picked_die(faces, formula, unit, location, config, diagnostics);
 
-        Ok(book)
+        label_table(table, head, labels, weights, location, config, diagnostics)
+            .with_formula(formula)
+            .with_choice(choice)
     }
 }
 
-impl RollTables {
-    fn handle_chapter(
-        &self,
-        chapter: &mut Chapter,
-        head_separator: &str,
-        separator: &str,
-        warn_unusual_dice: bool,
-    ) {
-        let mut buf = String::with_capacity(chapter.content.len());
+/// Warns about an unusual die like a d7 picked for a table that is converted
+/// with it, `faces` being how many rows it has, or faces with `unit` "face"
+/// if its rows are weighted. Explicit dice and `default-die` aren't picked.
+fn warn_picked_die(
+    faces: usize,
+    formula: Formula,
+    unit: &str,
+    location: &str,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) {
+    if formula == Formula::die(faces)
+        && config.default_die != Some(faces)
+        && config.warn_unusual_dice != UnusualDice::Never
+        && !STANDARD_DICE.contains(&faces)
+    {
+        diagnostics.warn(
+            Warning::UnusualDie {
+                die: format!("d{}", faces),
+                suggestion: Some(standard_alternatives(faces, unit, &STANDARD_DICE)),
+            },
+            location,
+        );
+    }
+}
+
+/// Puts the die in the header and the faces in the first column, unless
+/// there isn't a label for every row or one of them is empty.
+fn label_table(
+    table: &mut MarkdownTable,
+    head: String,
+    labels: Vec<Vec<String>>,
+    weights: Vec<usize>,
+    location: &str,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) -> TableOutcome {
+    // Nothing is changed unless every row gets a label, so a failing
+    // generator can't leave the table half-converted.
+    if labels.len() != table.rows().len() {
+        diagnostics.warn(
+            Warning::LabelCount {
+                labels: labels.len(),
+                rows: table.rows().len(),
+            },
+            location,
+        );
+        return TableOutcome::Unchanged;
+    }
+    if labels
+        .iter()
+        .any(|faces| faces.is_empty() || faces.iter().any(|f| f.is_empty()))
+    {
+        diagnostics.warn(Warning::EmptyLabel, location);
+        return TableOutcome::Unchanged;
+    }
+
+    let rows: Vec<_> = labels.into_iter().zip(weights).collect();
+    let (labels, weights): (Vec<_>, Vec<_>) = match config.merge_duplicates {
+        MergeDuplicates::Never => rows,
+        MergeDuplicates::Adjacent => merge_rows(table, rows, false),
+        MergeDuplicates::All => merge_rows(table, rows, true),
+    }
+    .into_iter()
+    .unzip();
 
-        let mut events = Parser::new_ext(&chapter.content, Options::ENABLE_TABLES);
+    replace_plain_text(&mut table.head_mut()[0], head.clone());
+    mark_up_dice(&mut table.head_mut()[0], config);
 
-        let mut state = cmark(iter::empty::<Event>(), &mut buf, None).unwrap();
+    for (faces, row) in labels.iter().zip(table.rows_mut()) {
+        row[0] = face_events(faces, config);
+    }
 
-        while let Some(ev) = events.next() {
-            if let Event::Start(Tag::Table(alignment)) = ev {
-                let mut table = MarkdownTable::new(alignment, &mut events);
+    if config.warn_duplicates && labels.len() >= config.duplicates_min_rows {
+        warn_duplicates(table, &labels, location, config, diagnostics);
+    }
 
-                if table.head()[0] == [Event::Text("d".into())]
-                    && table.rows().iter().all(|row| row[0].is_empty())
-                {
-                    let count = table.rows().len();
-                    let (head, iter) =
-                        get_dice_iterator(count, head_separator, separator, warn_unusual_dice);
+    match (config.center_die_column, table.alignment[0]) {
+        (CenterDieColumn::Always, _) | (CenterDieColumn::OnlyUnset, Alignment::None) => {
+            table.alignment[0] = Alignment::Center
+        }
+        _ => {}
+    }
 
-                    table.head_mut()[0] = head;
+    TableOutcome::Converted {
+        die: head,
+        choice: DieChoice::Explicit,
+        weights,
+        formula: None,
+    }
+}
 
-                    for (i, row) in iter.zip(table.rows_mut()) {
-                        row[0] = i;
-                    }
-                }
+/// Gives the rows of a 2d6 table the sums of a preset of bands, e.g. `2`,
+/// `3–5`, `6–8`, `9–11` and `12` for `reaction`. Unlike the other dice the
+/// table has to fit, so a wrong preset is an error.
+fn convert_bands(
+    table: &mut MarkdownTable,
+    preset: &str,
+    location: &str,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) -> Result<TableOutcome> {
+    let bands = config.bands.get(preset).ok_or_else(|| {
+        anyhow!(
+            "Unknown bands \"{}\" in {}, add them to [preprocessor.rolltables.bands]",
+            preset,
+            location
+        )
+    })?;
+    if bands.len() != table.rows().len() {
+        Err(anyhow!(
+            "The bands \"{}\" in {} have {} ranges but the table has {} rows",
+            preset,
+            location,
+            bands.len(),
+            table.rows().len()
+        ))?;
+    }
+    if !table.rows().iter().all(|row| is_blank(&row[0])) {
+        Err(anyhow!(
+            "The table with the bands \"{}\" in {} needs an empty first column",
+            preset,
+            location
+        ))?;
+    }
 
-                state = cmark(table.events_iter(), &mut buf, Some(state)).unwrap();
+    let labels: Vec<Vec<String>> = bands
+        .iter()
+        .map(|&(first, last)| (first..=last).map(|sum| sum.to_string()).collect())
+        .collect();
+    // How many of the 36 rolls of 2d6 have each sum.
+    let weights = bands
+        .iter()
+        .map(|&(first, last)| (first..=last).map(|sum| 6 - sum.abs_diff(7) as usize).sum())
+        .collect();
+
+    let formula = Formula::Sum {
+        dice: 2,
+        sides: 6,
+        modifier: 0,
+    };
+    Ok(label_table(
+        table,
+        "2d6".into(),
+        labels,
+        weights,
+        location,
+        config,
+        diagnostics,
+    )
+    .with_formula(formula)
+    .with_choice(DieChoice::Bands))
+}
+
+/// Whether a `d` or `d100` table has chances like `15%` in its first column.
+fn has_percentages(table: &MarkdownTable) -> bool {
+    matches!(plain_text(&table.head()[0]).as_str(), "d" | "d100")
+        && table
+            .rows()
+            .iter()
+            .any(|row| plain_text(&row[0]).ends_with('%'))
+}
+
+/// Rolls a table with chances like `15%` in its first column with a d100,
+/// giving the rows consecutive ranges like `01–15` and `16–30`, 100 being
+/// `00`. Rows left empty share what the others leave. Chances that don't add
+/// up to 100% are an error.
+fn convert_percentages(
+    table: &mut MarkdownTable,
+    location: &str,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) -> Result<TableOutcome> {
+    let mut chances = vec![];
+    for row in table.rows() {
+        let text = plain_text(&row[0]);
+        chances.push(
+            match text.strip_suffix('%').map(|n| n.trim().parse::<usize>()) {
+                None if text.is_empty() => None,
+                Some(Ok(n)) if n > 0 => Some(n),
+                _ => Err(anyhow!(
+                    "\"{}\" in {} isn't a chance like \"15%\"",
+                    text,
+                    location
+                ))?,
+            },
+        );
+    }
+
+    let total: usize = chances.iter().flatten().sum();
+    let empty = chances.iter().filter(|chance| chance.is_none()).count();
+    if empty == 0 && total != 100 {
+        Err(anyhow!(
+            "The chances of the table in {} add up to {}%, not 100%",
+            location,
+            total
+        ))?;
+    }
+    // Every empty row needs at least 1%.
+    if total + empty > 100 {
+        let rows = format!("{} empty row{}", empty, if empty == 1 { "" } else { "s" });
+        match 100usize.checked_sub(total).filter(|&rest| rest > 0) {
+            Some(rest) => Err(anyhow!(
+                "The chances of the table in {} add up to {}%, leaving {}% for {}",
+                location,
+                total,
+                rest,
+                rows
+            ))?,
+            None => Err(anyhow!(
+                "The chances of the table in {} already add up to {}%, leaving nothing for {}",
+                location,
+                total,
+                rows
+            ))?,
+        }
+    }
+    let rest = 100 - total;
+    if empty > 0 && !rest.is_multiple_of(empty) {
+        diagnostics.warn(
+            Warning::UnevenFaces {
+                die: "d100".into(),
+                rows: empty,
+            },
+            location,
+        );
+    }
+
+    // The first empty rows get one more if they can't all get the same.
+    let mut empty_rows = 0;
+    let weights: Vec<usize> = chances
+        .iter()
+        .map(|chance| {
+            chance.unwrap_or_else(|| {
+                empty_rows += 1;
+                rest / empty + (empty_rows <= rest % empty) as usize
+            })
+        })
+        .collect();
+
+    let face = |n: usize| format!("{:02}", n % 100);
+    let mut next = 1;
+    let labels = weights
+        .iter()
+        .map(|&weight| {
+            let (first, last) = (next, next + weight - 1);
+            next += weight;
+            if first == last {
+                vec![face(first)]
             } else {
-                state = cmark(iter::once(ev), &mut buf, Some(state)).unwrap();
+                vec![format!(
+                    "{}{}{}",
+                    face(first),
+                    config.range_separator.as_deref().unwrap_or("–"),
+                    face(last)
+                )]
+            }
+        })
+        .collect();
+
+    Ok(label_table(
+        table,
+        "d100".into(),
+        labels,
+        weights,
+        location,
+        config,
+        diagnostics,
+    )
+    .with_formula(Formula::die(100))
+    .with_choice(DieChoice::Chances))
+}
+
+/// Whether the first column of a table is a die column for
+/// `add-die-column`, converted or not. A header with a die in it like `d66`,
+/// `d10d10` or `d⁶`, a marker, a custom die or nothing at all counts, so
+/// `Road2` does too and is left alone.
+fn has_die_column(table: &MarkdownTable, config: &Config) -> bool {
+    let text = plain_text(&table.head()[0]);
+    let head = strip_annotation(&text).to_string();
+    let chars: Vec<char> = head.chars().collect();
+    head.is_empty()
+        || config.markers.contains_key(&head)
+        || config.custom_dice.contains_key(&head)
+        || head == "dxd"
+        || head.starts_with("d:")
+        || chars.windows(2).any(|pair| {
+            pair[0] == 'd' && (pair[1].is_ascii_digit() || "⁰¹²³⁴⁵⁶⁷⁸⁹".contains(pair[1]))
+        })
+}
+
+/// Gives the last row the `reserved` highest faces and spreads the others
+/// over the rest, for `reserve-top`. The die is the one in the header, a
+/// d100 for `percentile` markers, or else the smallest standard die the other
+/// rows split evenly, or just have enough faces of. Faces of a d100 are
+/// written like percentages, `01–49` and `99–00`.
+fn convert_reserved(
+    table: &mut MarkdownTable,
+    reserved: usize,
+    location: &str,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) -> Result<TableOutcome> {
+    let rows = table.rows().len();
+    if rows == 0 || !table.rows().iter().all(|row| is_blank(&row[0])) {
+        return Ok(TableOutcome::Unchanged);
+    }
+    if rows < 2 || table.weights().is_some() {
+        Err(anyhow!(
+            "reserve-top in {} needs a table of at least two rows with one face each",
+            location
+        ))?;
+    }
+    let normal_rows = rows - 1;
+
+    let marker = plain_text(&table.head()[0]);
+    let (head, sides, modifier) = match (ExplicitDie::parse(&marker), config.markers.get(&marker)) {
+        (Some(die), _) if die.sides <= MAX_FACES as u64 => {
+            (marker.clone(), die.sides as usize, die.modifier)
+        }
+        (Some(_), _) => {
+            diagnostics.warn(Warning::TooManyFaces { die: marker }, location);
+            return Ok(TableOutcome::Unchanged);
+        }
+        (None, Some(MarkerPolicy::Percentile)) => ("d100".into(), 100, 0),
+        (None, Some(_)) => {
+            let fits = STANDARD_DICE
+                .iter()
+                .copied()
+                .filter(|&die| die >= normal_rows + reserved);
+            let even = fits
+                .clone()
+                .find(|&die| (die - reserved).is_multiple_of(normal_rows));
+            match even.or_else(|| fits.clone().next()) {
+                Some(die) => (format!("d{}", die), die, 0),
+                None => Err(anyhow!(
+                    "No standard die has the {} faces reserve-top={} in {} needs, \
+                     write the die in the header",
+                    normal_rows + reserved,
+                    reserved,
+                    location
+                ))?,
             }
         }
+        (None, None) => return Ok(TableOutcome::Unchanged),
+    };
 
-        chapter.content = buf;
+    if reserved + normal_rows > sides {
+        Err(anyhow!(
+            "reserve-top={} in {} leaves fewer faces of {} than the {} other rows",
+            reserved,
+            location,
+            head,
+            normal_rows
+        ))?;
+    }
+    let rest = sides - reserved;
+    if !rest.is_multiple_of(normal_rows) {
+        diagnostics.warn(
+            Warning::UnevenFaces {
+                die: head.clone(),
+                rows: normal_rows,
+            },
+            location,
+        );
+    }
+
+    // The first rows get one more face if they can't all get the same.
+    let weights: Vec<usize> = (0..normal_rows)
+        .map(|row| rest / normal_rows + (row < rest % normal_rows) as usize)
+        .chain(Some(reserved))
+        .collect();
+    let percentile = sides == 100 && modifier == 0;
+    let face = |n: usize| {
+        if percentile {
+            format!("{:02}", n % 100)
+        } else {
+            (n as i64 + modifier).to_string()
+        }
+    };
+    let range = |first: usize, last: usize| {
+        format!(
+            "{}{}{}",
+            face(first),
+            config.range_separator.as_deref().unwrap_or("–"),
+            face(last)
+        )
+    };
+
+    let mut next = 1;
+    let labels = weights
+        .iter()
+        .enumerate()
+        .map(|(row, &weight)| {
+            let (first, last) = (next, next + weight - 1);
+            next += weight;
+            match (first == last, percentile || row == normal_rows) {
+                (true, _) => vec![face(first)],
+                (false, true) => vec![range(first, last)],
+                (false, false) => (first..=last).map(face).collect(),
+            }
+        })
+        .collect();
+
+    let formula = Formula::Sum {
+        dice: 1,
+        sides,
+        modifier,
+    };
+    let choice = match ExplicitDie::parse(&marker) {
+        Some(_) => DieChoice::Explicit,
+        None => DieChoice::Die(sides),
+    };
+    Ok(
+        label_table(table, head, labels, weights, location, config, diagnostics)
+            .with_formula(formula)
+            .with_choice(choice),
+    )
+}
+
+/// Spreads the faces of a die written in the header, e.g. `d20`, over the
+/// rows.
+fn convert_explicit(
+    table: &mut MarkdownTable,
+    marker: &str,
+    die: ExplicitDie,
+    location: &str,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) -> TableOutcome {
+    let rows = table.rows().len();
+    if rows == 0 || !table.rows().iter().all(|row| is_blank(&row[0])) {
+        return TableOutcome::Unchanged;
+    }
+
+    // Usually a typo for d10 or d100.
+    if die.sides < 2 {
+        diagnostics.warn(
+            Warning::InvalidDie {
+                die: marker.into(),
+                suggestion: suggest_die(marker, rows, &STANDARD_DICE),
+                rows,
+            },
+            location,
+        );
+        return TableOutcome::Unchanged;
+    }
+
+    if die.sides < rows as u64 {
+        diagnostics.warn(
+            Warning::TooFewFaces {
+                die: marker.into(),
+                rows,
+            },
+            location,
+        );
+        return TableOutcome::Unchanged;
+    }
+    // There is a limit on how many faces are written out.
+    if die.sides > MAX_FACES as u64 {
+        diagnostics.warn(Warning::TooManyFaces { die: marker.into() }, location);
+        return TableOutcome::Unchanged;
+    }
+    let sides = die.sides as usize;
+
+    if !sides.is_multiple_of(rows) {
+        diagnostics.warn(
+            Warning::UnevenFaces {
+                die: marker.into(),
+                rows,
+            },
+            location,
+        );
+    }
+    // Whoever writes a d7 in the header has one.
+    if config.warn_unusual_dice == UnusualDice::Always && !STANDARD_DICE.contains(&sides) {
+        diagnostics.warn(
+            Warning::UnusualDie {
+                die: marker.into(),
+                suggestion: Some(standard_alternatives(rows, "row", &STANDARD_DICE))
+                    .filter(|_| sides == rows),
+            },
+            location,
+        );
+    }
+
+    // The first rows get one more face if they can't all get the same.
+    let mut next = 1;
+    let labels: Vec<Vec<String>> = (0..rows)
+        .map(|row| {
+            let faces = sides / rows + (row < sides % rows) as usize;
+            let labels = (next..next + faces)
+                .map(|face| (face as i64 + die.modifier).to_string())
+                .collect();
+            next += faces;
+            labels
+        })
+        .collect();
+
+    let weights = labels.iter().map(Vec::len).collect();
+    label_table(
+        table,
+        marker.into(),
+        labels,
+        weights,
+        location,
+        config,
+        diagnostics,
+    )
+    .with_formula(Formula::Sum {
+        dice: 1,
+        sides,
+        modifier: die.modifier,
+    })
+}
+
+/// Fills in a `dxd` table where the rows are one die and the columns after
+/// the first another, like a d66 where both dice are read separately.
+fn convert_matrix(
+    table: &mut MarkdownTable,
+    location: &str,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) -> TableOutcome {
+    let rows = table.rows().len();
+    let columns = table.head().len() - 1;
+    if rows == 0
+        || columns == 0
+        || !table.rows().iter().all(|row| is_blank(&row[0]))
+        || !table.head()[1..]
+            .iter()
+            .all(|cell| plain_text(cell).is_empty())
+    {
+        return TableOutcome::Unchanged;
+    }
+
+    let row_die = format!("d{}", rows);
+    let column_die = format!("d{}", columns);
+    for (count, die, unit) in [(rows, &row_die, "row"), (columns, &column_die, "column")] {
+        if config.warn_unusual_dice != UnusualDice::Never && !STANDARD_DICE.contains(&count) {
+            diagnostics.warn(
+                Warning::UnusualDie {
+                    die: die.clone(),
+                    suggestion: Some(standard_alternatives(count, unit, &STANDARD_DICE)),
+                },
+                location,
+            );
+        }
+    }
+
+    let corner = config
+        .matrix_corner
+        .replace("{row}", &row_die)
+        .replace("{column}", &column_die);
+    replace_plain_text(&mut table.head_mut()[0], corner);
+    mark_up_dice(&mut table.head_mut()[0], config);
+    for (i, cell) in table.head_mut()[1..].iter_mut().enumerate() {
+        *cell = face_events(&[(i + 1).to_string()], config);
+    }
+    for (i, row) in table.rows_mut().iter_mut().enumerate() {
+        row[0] = face_events(&[(i + 1).to_string()], config);
+    }
+
+    match (config.center_die_column, table.alignment[0]) {
+        (CenterDieColumn::Always, _) | (CenterDieColumn::OnlyUnset, Alignment::None) => {
+            table.alignment[0] = Alignment::Center
+        }
+        _ => {}
+    }
+
+    TableOutcome::Converted {
+        die: format!("{}x{}", row_die, column_die),
+        choice: DieChoice::Matrix,
+        weights: vec![columns; rows],
+        formula: None,
+    }
+}
+
+/// One row per unique face of a custom die, `None` if the table can't use
+/// it.
+fn custom_die_labels(
+    name: &str,
+    table: &MarkdownTable,
+    location: &str,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) -> Option<(String, Vec<Vec<String>>, Vec<usize>)> {
+    if !table.rows().iter().all(|row| is_blank(&row[0])) {
+        return None;
+    }
+
+    let faces = match config.custom_dice.get(name) {
+        Some(faces) => faces,
+        None => {
+            diagnostics.warn(Warning::UnknownCustomDie { name: name.into() }, location);
+            return None;
+        }
+    };
+
+    let mut unique: Vec<(&str, usize)> = vec![];
+    for face in faces {
+        match unique.iter_mut().find(|(f, _)| f == face) {
+            Some((_, count)) => *count += 1,
+            None => unique.push((face, 1)),
+        }
+    }
+
+    if unique.len() != table.rows().len() {
+        diagnostics.warn(
+            Warning::CustomDieRows {
+                name: name.into(),
+                faces: unique.len(),
+                rows: table.rows().len(),
+            },
+            location,
+        );
+        return None;
+    }
+
+    let weights = unique.iter().map(|(_, count)| *count).collect();
+    let labels = unique
+        .into_iter()
+        .map(|(face, count)| match count {
+            1 => vec![face.to_string()],
+            _ => vec![config
+                .custom_face_label
+                .replace("{face}", face)
+                .replace("{count}", &count.to_string())],
+        })
+        .collect();
+    Some((name.into(), labels, weights))
+}
+
+/// Removes rows with the same result as an earlier one, adjacent unless
+/// `all`, and gives their faces and weight to it.
+fn merge_rows(
+    table: &mut MarkdownTable,
+    labels: Vec<(Vec<String>, usize)>,
+    all: bool,
+) -> Vec<(Vec<String>, usize)> {
+    let texts: Vec<String> = table.rows().iter().map(|row| result_text(row)).collect();
+    let mut kept: Vec<usize> = vec![];
+    let mut merged: Vec<(Vec<String>, usize)> = vec![];
+
+    for (i, (faces, weight)) in labels.into_iter().enumerate() {
+        let same = |k: &usize| !texts[i].is_empty() && texts[*k] == texts[i];
+        let target = if all {
+            kept.iter().position(same)
+        } else {
+            kept.last().filter(|k| same(k)).map(|_| kept.len() - 1)
+        };
+
+        match target {
+            Some(target) => {
+                merged[target].0.extend(faces);
+                merged[target].1 += weight;
+            }
+            None => {
+                kept.push(i);
+                merged.push((faces, weight));
+            }
+        }
+    }
+
+    table.retain_rows(&kept);
+    merged
+}
+
+/// The text of the results of a row with whitespace normalized, empty if
+/// there is none, e.g. only images.
+fn result_text(row: &[Vec<Event>]) -> String {
+    let cells: Vec<String> = row[1..].iter().map(|cell| cell_text(cell)).collect();
+    let text = cells
+        .join(" | ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if text.chars().all(|c| c == '|' || c == ' ') {
+        String::new()
+    } else {
+        text
+    }
+}
+
+/// Warns about every result that appears in more than one row.
+fn warn_duplicates(
+    table: &MarkdownTable,
+    labels: &[Vec<String>],
+    location: &str,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut results: Vec<(String, Vec<String>)> = vec![];
+    let mut index = HashMap::new();
+
+    for (faces, row) in labels.iter().zip(table.rows()) {
+        let text = result_text(row);
+        // Rows without any text can't be compared.
+        if text.is_empty() {
+            continue;
+        }
+        let key = if config.duplicates_ignore_case {
+            fold_case(&text)
+        } else {
+            text.clone()
+        };
+
+        let i = *index.entry(key).or_insert_with(|| {
+            results.push((text, vec![]));
+            results.len() - 1
+        });
+        results[i].1.push(faces.join(", "));
+    }
+
+    for (text, faces) in results.into_iter().filter(|(_, faces)| faces.len() > 1) {
+        diagnostics.warn(Warning::Duplicate { text, faces }, location);
+    }
+}
+
+/// The first differing line of `old` and `new` with a line around it.
+fn context_diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let first = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let start = first.saturating_sub(1);
+
+    let mut diff = vec![];
+    for line in old.iter().skip(start).take(first + 2 - start) {
+        diff.push(format!("- {}", line));
+    }
+    for line in new.iter().skip(start).take(first + 2 - start) {
+        diff.push(format!("+ {}", line));
+    }
+    diff.join("\n")
+}
+
+/// The whitespace and block quote markers before the second line of
+/// `original`, which is where continuation lines of a nested block start.
+/// Whether a line is empty apart from whitespace and the `>` of block
+/// quotes.
+fn is_blank_line(line: &str) -> bool {
+    line.chars().all(|c| c.is_whitespace() || c == '>')
+}
+
+fn indent_of(original: &str) -> String {
+    original
+        .lines()
+        .nth(1)
+        .unwrap_or_default()
+        .chars()
+        .take_while(|c| c.is_whitespace() || *c == '>')
+        .collect()
+}
+
+/// The extensions mdBook renders with, so e.g. `~~struck~~` or a footnote
+/// reference in a cell that is written again stays markup instead of being
+/// escaped. Curly quotes are applied to the text mdBook renders, so they
+/// don't change how chapters are parsed here.
+pub(crate) fn parser_options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+}
+
+/// Whether the first cell of a head is one of the markers or die headers
+/// that make a roll table.
+fn looks_like_marker(marker: &str, config: &Config) -> bool {
+    let marker = strip_annotation(marker);
+    config.markers.contains_key(marker)
+        || marker == "dxd"
+        || marker.starts_with("d:")
+        || marker.starts_with("2d6!")
+        || ExplicitDie::parse(marker).is_some()
+}
+
+/// Puts a blank line between a table and a roll table written right below
+/// it, which would otherwise be read as more rows of the first one.
+fn separate_stacked_tables<'s>(source: &'s str, config: &Config) -> Cow<'s, str> {
+    let mut starts = vec![];
+    for (ev, range) in Parser::new_ext(source, parser_options()).into_offset_iter() {
+        if let Event::Start(Tag::Table(_)) = ev {
+            let heads = stacked_heads(&source[range.clone()]);
+            starts.extend(
+                heads
+                    .into_iter()
+                    .filter(|(_, marker)| looks_like_marker(marker, config))
+                    .map(|(offset, _)| range.start + offset),
+            );
+        }
+    }
+    if starts.is_empty() {
+        return Cow::Borrowed(source);
+    }
+
+    let mut separated = String::with_capacity(source.len() + starts.len() * 2);
+    let mut copied = 0;
+    for start in starts {
+        // Tables in block quotes keep the `>` on the blank line.
+        let line = &source[start..];
+        let row = line.trim_start_matches(|c: char| c == '>' || c.is_whitespace());
+        let prefix = &line[..line.len() - row.len()];
+        separated.push_str(&source[copied..start]);
+        separated.push_str(prefix.trim_end());
+        separated.push_str(line_ending(source, config));
+        copied = start;
+    }
+    separated.push_str(&source[copied..]);
+    Cow::Owned(separated)
+}
+
+/// The path of a chapter for diagnostics, or its name for draft chapters.
+fn location(chapter: &Chapter) -> String {
+    match &chapter.path {
+        Some(path) => display_path(path),
+        None => format!("chapter \"{}\"", chapter.name),
+    }
+}
+
+/// `path`, relative to `src` like mdBook's, with `/` between directories
+/// even if it was written with `\`, so messages and the manifest read the
+/// same on every OS.
+fn display_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn is_escaped_marker(cell: &[Event]) -> bool {
+    // The parser leaves an empty text after a code span.
+    let events: Vec<&Event> = cell
+        .iter()
+        .filter(|ev| {
+            !matches!(ev, Event::Text(text) if text.is_empty()) && **ev != Event::SoftBreak
+        })
+        .collect();
+    match events[..] {
+        [Event::Code(code)] => code.as_ref() == "d",
+        [Event::Text(text)] => text.as_ref() == "d\\",
+        _ => false,
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-struct MarkdownTable<'a> {
-    alignment: Vec<Alignment>,
-    content: Vec<Vec<Vec<Event<'a>>>>,
+/// Joins the faces of a row with commas, wrapping them with `<br>` if
+/// `face-wrap` is enabled. Consecutive faces are written as a range, see
+/// [`face_runs`].
+fn face_events<'a>(faces: &[String], config: &Config) -> Vec<Event<'a>> {
+    let label = |text: String| match config.face_style {
+        FaceStyle::Plain => Event::Text(text.into()),
+        FaceStyle::Code => Event::Code(text.into()),
+    };
+
+    let faces = face_runs(faces, config);
+
+    let group_size = match config.face_wrap {
+        FaceWrap::None => faces.len(),
+        FaceWrap::Br => config.face_wrap_size,
+    };
+
+    let mut events = vec![];
+    for (i, group) in faces.chunks(group_size.max(1)).enumerate() {
+        if i > 0 {
+            events.push(Event::Text(",".into()));
+            events.push(Event::Html("<br>".into()));
+        }
+        events.push(label(group.join(", ")));
+    }
+    events
 }
 
-impl<'a> MarkdownTable<'a> {
-    fn new(alignment: Vec<Alignment>, parser: &mut Parser<'a>) -> Self {
-        let mut content = vec![];
+/// A converted table with two columns written as one for
+/// `single-column-output`, the faces of every row in front of its result
+/// like `**14** Bandits`, and the die in front of the header.
+fn single_column_markdown(table: &MarkdownTable, indent: &str, config: &Config) -> String {
+    let label = |faces: String| {
+        config
+            .single_column_label
+            .replace("{faces}", &escape_label(&faces))
+    };
+    let rows: Vec<String> = table
+        .content
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let faces = match i {
+                0 => cell_text(&row[0]),
+                _ => faces(&row[0]).join(", "),
+            };
+            let result = row.get(1).map(|cell| table.cell_to_markdown(cell));
+            match (faces.is_empty(), result) {
+                (true, result) => result.unwrap_or_default(),
+                (false, Some(result)) if !result.is_empty() => {
+                    format!("{} {}", label(faces), result)
+                }
+                (false, _) => label(faces),
+            }
+        })
+        .collect();
+
+    let content = rows
+        .iter()
+        .map(|cell| vec![markdown_events(cell)])
+        .collect();
+    MarkdownTable::from_content(vec![table.alignment[1]], content, None)
+        .to_markdown(indent, config.pretty_tables)
+}
 
-        loop {
-            match parser.next().unwrap() {
-                Event::Start(Tag::TableHead | Tag::TableRow) => content.push(vec![]),
-                Event::Start(Tag::TableCell) => content.last_mut().unwrap().push(vec![]),
-                Event::End(Tag::TableHead | Tag::TableRow | Tag::TableCell) => {}
-                Event::End(Tag::Table(_)) => break Self { alignment, content },
-                ev => content.last_mut().unwrap().last_mut().unwrap().push(ev),
+/// Ends `output` with a line break only if `source` does, whatever was
+/// rewritten or added at its end, so chapters don't gain or lose one.
+fn keep_final_newline(source: &str, output: &mut String, config: &Config) {
+    if source.ends_with('\n') {
+        if !output.ends_with('\n') {
+            output.push_str(line_ending(source, config));
+        }
+    } else {
+        while output.ends_with('\n') {
+            output.pop();
+            if output.ends_with('\r') {
+                output.pop();
             }
         }
     }
+}
+
+/// The text of a paragraph that is only bold text, like a caption.
+fn caption_text(events: &[Event]) -> Option<String> {
+    let inner = match events {
+        [Event::Start(Tag::Strong), inner @ .., Event::End(Tag::Strong)] => inner,
+        _ => return None,
+    };
+    let mut text = String::new();
+    for ev in inner {
+        match ev {
+            Event::Text(t) | Event::Code(t) => text.push_str(t),
+            Event::SoftBreak => text.push(' '),
+            _ => return None,
+        }
+    }
+    Some(text.trim().to_string())
+}
+
+/// The name of a name directive, or else the generated one. The generated
+/// name is made either way, so naming a table doesn't rename the ones after
+/// it.
+fn pick_name(explicit: Option<String>, generated: String) -> String {
+    explicit.unwrap_or(generated)
+}
+
+/// Splits a leading byte order mark off a chapter, so it doesn't become
+/// part of the first block.
+fn split_bom(content: &str) -> (&str, &str) {
+    match content.strip_prefix('\u{feff}') {
+        Some(rest) => (&content[..content.len() - rest.len()], rest),
+        None => ("", content),
+    }
+}
+
+/// Splits the comment of `debug-keep-original` off the end of a chapter
+/// converted before. The chapter in it has no "--", so the comment ends
+/// at the first "-->".
+fn split_kept_original(source: &str) -> (&str, Option<&str>) {
+    match source.rfind(KEPT_ORIGINAL) {
+        Some(start)
+            if source[..start].ends_with('\n')
+                && source[start..].trim_end().ends_with("-->")
+                && source[start..].matches("-->").count() == 1 =>
+        {
+            (&source[..start], Some(&source[start..]))
+        }
+        _ => (source, None),
+    }
+}
 
-    fn head(&self) -> &[Vec<Event<'a>>] {
-        &self.content[0][..]
+/// `\r\n` if `preserve-line-endings` is set and `source` uses them, for the
+/// lines written in its place.
+fn line_ending(source: &str, config: &Config) -> &'static str {
+    if config.preserve_line_endings && source.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
     }
+}
 
-    fn head_mut(&mut self) -> &mut [Vec<Event<'a>>] {
-        &mut self.content[0][..]
+/// Marks up the dice in the text of a header cell with `die-markup`.
+fn mark_up_dice(cell: &mut Vec<Event>, config: &Config) {
+    if config.die_markup == DieMarkup::Plain {
+        return;
     }
+    *cell = cell
+        .drain(..)
+        .flat_map(|ev| match ev {
+            Event::Text(text) => die_events(&text, config.die_markup),
+            ev => vec![ev],
+        })
+        .collect();
+}
 
-    fn rows(&self) -> &[Vec<Vec<Event<'a>>>] {
-        &self.content[1..]
+/// Splits `text` into the dice expressions in it, marked up with
+/// `die-markup`, and the text around them.
+fn die_events(text: &str, markup: DieMarkup) -> Vec<Event<'static>> {
+    let mut events = vec![];
+    let mut copied = 0;
+    for dice in find_dice(text) {
+        if copied < dice.start {
+            events.push(Event::Text(text[copied..dice.start].to_string().into()));
+        }
+        let die = &text[dice.clone()];
+        match markup {
+            DieMarkup::Plain => events.push(Event::Text(die.to_string().into())),
+            DieMarkup::Span => events.extend([
+                Event::Html("<span class=\"die\">".into()),
+                Event::Text(die.to_string().into()),
+                Event::Html("</span>".into()),
+            ]),
+            DieMarkup::Superscript => events.push(Event::Text(superscript(die).into())),
+        }
+        copied = dice.end;
+    }
+    if copied < text.len() {
+        events.push(Event::Text(text[copied..].to_string().into()));
     }
+    events
+}
+
+/// Writes the faces of a die as superscript, e.g. 2d⁶+1.
+fn superscript(die: &str) -> String {
+    let mut faces = false;
+    die.chars()
+        .map(|c| {
+            match c {
+                'd' => faces = true,
+                '+' | '-' => faces = false,
+                _ => {}
+            }
+            match c.to_digit(10) {
+                Some(digit) if faces => "⁰¹²³⁴⁵⁶⁷⁸⁹".chars().nth(digit as usize).unwrap(),
+                _ => c,
+            }
+        })
+        .collect()
+}
+
+/// Writes every run of at least two consecutive faces as a range, e.g.
+/// `1, 2, 3, 5` as `1–3` and `5`, if `range-separator` is set. The
+/// `00` of a d100 follows `99`.
+fn face_runs(faces: &[String], config: &Config) -> Vec<String> {
+    let separator = match &config.range_separator {
+        Some(separator) => separator,
+        None => return faces.to_vec(),
+    };
+    let value = |face: &str| match face {
+        "00" => Some(100),
+        face => face.parse::<u64>().ok(),
+    };
+    let follows = |face: &str, previous: &str| matches!((value(previous), value(face)), (Some(a), Some(b)) if b == a + 1);
 
-    fn rows_mut(&mut self) -> &mut [Vec<Vec<Event<'a>>>] {
-        &mut self.content[1..]
+    let mut runs: Vec<(&String, &String)> = vec![];
+    for face in faces {
+        match runs.last_mut() {
+            Some((_, last)) if follows(face, last) => *last = face,
+            _ => runs.push((face, face)),
+        }
     }
+    runs.into_iter()
+        .map(|(first, last)| match first == last {
+            true => first.clone(),
+            false => format!("{}{}{}", first, separator, last),
+        })
+        .collect()
+}
 
-    fn events_iter(&'a self) -> impl Iterator<Item = Event<'a>> {
-        fn cell_events_iter<'b>(cell: &'b Vec<Event<'b>>) -> impl Iterator<Item = Event<'b>> {
-            iter::empty()
-                .chain(iter::once(Event::Start(Tag::TableCell)))
-                .chain(cell.iter().cloned())
-                .chain(iter::once(Event::End(Tag::TableCell)))
+/// Puts the die in the header and the faces in the empty first cells of a
+/// raw HTML table (`html-table-support`). Returns the new HTML, the table,
+/// which is still to be named, the number of faces of every row and how the
+/// die was picked.
+fn convert_html_table(
+    html: &str,
+    location: &str,
+    config: &Config,
+    diagnostics: &mut Diagnostics,
+) -> Option<(String, RollTable, Vec<usize>, DieChoice)> {
+    let table = match HtmlTable::parse(html) {
+        Ok(Some(table)) => table,
+        Ok(None) => return None,
+        Err(reason) => {
+            diagnostics.warn(Warning::MalformedHtmlTable { reason }, location);
+            return None;
         }
+    };
+
+    let rows = table.rows.len();
+    let (choice, head, iter, formula) =
+        get_dice_iterator(rows, MarkerPolicy::Auto, config, diagnostics, location);
+    let labels: Vec<_> = iter.collect();
+    if labels.len() != rows {
+        diagnostics.warn(
+            Warning::LabelCount {
+                labels: labels.len(),
+                rows,
+            },
+            location,
+        );
+        return None;
+    }
+    if labels
+        .iter()
+        .any(|faces| faces.is_empty() || faces.iter().any(|f| f.is_empty()))
+    {
+        diagnostics.warn(Warning::EmptyLabel, location);
+        return None;
+    }
 
-        iter::empty()
-            .chain(iter::once(Event::Start(Tag::Table(self.alignment.clone()))))
-            // Head
-            .chain(iter::once(Event::Start(Tag::TableHead)))
-            .chain(self.head().iter().flat_map(cell_events_iter))
-            .chain(iter::once(Event::End(Tag::TableHead)))
-            // Rows
-            .chain(self.rows().iter().flat_map(|row| {
-                // Row
-                iter::empty()
-                    .chain(iter::once(Event::Start(Tag::TableRow)))
-                    .chain(row.iter().flat_map(cell_events_iter))
-                    .chain(iter::once(Event::End(Tag::TableRow)))
-            }))
-            .chain(iter::once(Event::End(Tag::Table(self.alignment.clone()))))
+    warn_picked_die(rows, formula, "row", location, config, diagnostics);
+
+    let weights = labels.iter().map(Vec::len).collect();
+    let labels: Vec<_> = labels
+        .iter()
+        .map(|faces| face_events(faces, config))
+        .collect();
+    let cell = |range: &Range<usize>| html[range.clone()].trim().to_string();
+    let roll_table = RollTable {
+        name: String::new(),
+        die: head.clone(),
+        formula: None,
+        columns: table.head[1..].iter().map(cell).collect(),
+        rows: table
+            .rows
+            .iter()
+            .zip(&labels)
+            .map(|(row, label)| RollRow {
+                faces: faces(label),
+                bounds: vec![],
+                cells: row[1..].iter().map(cell).collect(),
+            })
+            .collect(),
+    }
+    .with_formula(Some(formula), config);
+
+    let cells = iter::once((&table.head[0], die_events(&head, config.die_markup)))
+        .chain(table.rows.iter().map(|row| &row[0]).zip(labels));
+    let mut converted = String::with_capacity(html.len());
+    let mut copied = 0;
+    for (range, events) in cells {
+        // Whitespace around the contents, like the line breaks of a
+        // hard-wrapped `<th>`, is kept.
+        let text = &html[range.clone()];
+        let start = range.start + text.len() - text.trim_start().len();
+        converted.push_str(&html[copied..start]);
+        pulldown_cmark::html::push_html(&mut converted, events.into_iter());
+        copied = start.max(range.start + text.trim_end().len());
     }
+    converted.push_str(&html[copied..]);
+
+    Some((converted, roll_table, weights, choice))
 }
 
+type DiceIterator<'a> = Box<dyn Iterator<Item = Vec<String>> + 'a>;
+
+/// The most faces an explicit die in a header can have.
+const MAX_FACES: usize = 10_000;
+
+/// The start of the comment of `debug-keep-original`.
+const KEPT_ORIGINAL: &str = "<!-- rolltables-original:";
+
+/// A "d" in the header of a table whose marker was escaped.
+const UNESCAPED_MARKER: &str = "&#100;";
+
+/// The die [`select_die`] picks for `count` rows, with its header, faces and
+/// formula.
 fn get_dice_iterator<'a>(
     count: usize,
-    head_separator: &'a str,
-    separator: &'a str,
-    warn_unusual_dice: bool,
-) -> (
-    Vec<Event<'a>>,
-    Box<dyn Iterator<Item = Vec<Event<'a>>> + 'a>,
-) {
-    fn map_string_to_event<'b>(
-        iter: impl Iterator<Item = String> + 'b,
-    ) -> Box<dyn Iterator<Item = Vec<Event<'b>>> + 'b> {
-        Box::new(iter.map(|s| vec![Event::Text(s.into())]))
+    policy: MarkerPolicy,
+    config: &'a Config,
+    diagnostics: &mut Diagnostics,
+    location: &str,
+) -> (DieChoice, String, DiceIterator<'a>, Formula) {
+    let options = config.dice_options(policy);
+    let choice = select_die(count, &options);
+    let (head, faces, formula) = dice_for_choice(choice, count, config, diagnostics, location);
+    if config.verbose {
+        for (a, b) in passed_over(count, &options) {
+            diagnostics.note(
+                format!(
+                    "Picked {} for {} rows rather than d{}{}{}, its largest die is smaller",
+                    head, count, a, config.head_separator, b
+                ),
+                location,
+            );
+        }
+    }
+    if let Some(die) = config.default_die {
+        if !die.is_multiple_of(count) && marker_die(count, policy).is_none() {
+            let sides = match choice {
+                DieChoice::Pair(a, b) => a * b,
+                DieChoice::Die(sides) => sides,
+                _ => count,
+            };
+            diagnostics.warn(
+                Warning::UnevenDefaultDie {
+                    die,
+                    rows: count,
+                    instead: choice.describe(&head, &vec![sides / count; count]),
+                },
+                location,
+            );
+        }
+    }
+
+    (choice, head, faces, formula)
+}
+
+/// The header, faces and formula of `choice` for `count` rows.
+fn dice_for_choice<'a>(
+    choice: DieChoice,
+    count: usize,
+    config: &'a Config,
+    diagnostics: &mut Diagnostics,
+    location: &str,
+) -> (String, DiceIterator<'a>, Formula) {
+    fn single_faces<'b>(iter: impl Iterator<Item = String> + 'b) -> DiceIterator<'b> {
+        Box::new(iter.map(|s| vec![s]))
     }
 
-    let combined_dice = |a: usize, b: usize| {
+    // Every row gets the same number of consecutive faces.
+    let grouped = |die: usize| -> (String, DiceIterator) {
+        let per_row = die / count;
+        (
+            format!("d{}", die),
+            Box::new((0..count).map(move |row| {
+                (row * per_row + 1..=(row + 1) * per_row)
+                    .map(|face| face.to_string())
+                    .collect()
+            })),
+        )
+    };
+
+    let head_separator = &config.head_separator;
+    let separator = &config.separator;
+
+    let mut combined_dice = |a: usize, b: usize| -> (String, DiceIterator) {
+        // With an empty separator "111" could be both 1 and 11 or 11 and 1.
+        if a.max(b) >= 10 && separator.is_empty() {
+            diagnostics.warn(
+                Warning::AmbiguousSeparator {
+                    die: format!("d{}{}{}", a, head_separator, b),
+                },
+                location,
+            );
+        }
+        if a.max(b) >= 10 && head_separator.is_empty() {
+            diagnostics.warn(
+                Warning::AmbiguousHeadSeparator {
+                    die: format!("d{}{}", a, b),
+                },
+                location,
+            );
+        }
+
         (
-            vec![Event::Text(format!("d{}{}{}", a, head_separator, b).into())],
-            map_string_to_event(
+            format!("d{}{}{}", a, head_separator, b),
+            single_faces(
                 (1..=a)
                     .flat_map(move |die| iter::repeat(die).zip(1..=b))
                     .map(move |(n0, n1)| format!("{}{}{}", n0, separator, n1)),
@@ -184,33 +2908,25 @@ fn get_dice_iterator<'a>(
         )
     };
 
-    match count {
-        16 => combined_dice(4, 4),
-        24 => combined_dice(6, 4),
-        32 => combined_dice(8, 4),
-        36 => combined_dice(6, 6),
-        48 => combined_dice(8, 6),
-        64 => combined_dice(8, 8),
-        3 => (
-            vec![Event::Text("d6".into())],
-            map_string_to_event(
-                vec![
-                    String::from("1, 2"),
-                    String::from("3, 4"),
-                    String::from("5, 6"),
-                ]
-                .into_iter(),
-            ),
-        ),
-        _ => {
-            if warn_unusual_dice && ![4, 6, 8, 10, 12, 20, 100].contains(&count) {
-                eprintln!("Warning: Roll table created with unusual dice: d{}", count);
+    let (head, faces) = match choice {
+        DieChoice::Pair(a, b) => {
+            let (head, faces) = combined_dice(a, b);
+            match config.d66_dice {
+                // The faces are the same, only what to reroll is noted.
+                D66Dice::D10 if (a, b) == (6, 6) => (head + &config.d66_d10_suffix, faces),
+                _ => (head, faces),
             }
-
-            (
-                vec![Event::Text(format!("d{}", count).into())],
-                map_string_to_event((1..=count).map(|i| format!("{}", i))),
-            )
         }
-    }
+        DieChoice::Die(die) if die != count => grouped(die),
+        _ => (
+            format!("d{}", count),
+            single_faces((1..=count).map(|i| format!("{}", i))),
+        ),
+    };
+    let formula = match choice {
+        DieChoice::Pair(a, b) => Formula::Pair(a, b),
+        DieChoice::Die(die) => Formula::die(die),
+        _ => Formula::die(count),
+    };
+    (head, faces, formula)
 }
@@ -0,0 +1,243 @@
+use crate::{
+    diagnostics::Report,
+    dice::DieChoice,
+    model::RollTable,
+    text::{fold_case, format_percent},
+    Chapter,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::HashMap, ops::Range, path::PathBuf};
+// Only used by the preprocessor and the command line.
+#[cfg(feature = "mdbook")]
+use {
+    crate::display_path,
+    anyhow::Context,
+    std::{collections::BTreeMap, fs, path::Path},
+};
+
+/// What is known about a converted roll table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TableInfo {
+    /// Named with [`TableNames`].
+    pub(crate) table: RollTable,
+    /// Path of the chapter, see [`crate::location`].
+    pub(crate) location: String,
+    /// Source file of the chapter, relative to `src`.
+    pub(crate) source_path: Option<PathBuf>,
+    /// Bytes of the original table in the chapter's content.
+    pub(crate) range: Range<usize>,
+    /// How many faces of the die every row has.
+    pub(crate) weights: Vec<usize>,
+    /// How the die was picked.
+    pub(crate) choice: DieChoice,
+}
+
+/// A table tagged with a `screen` directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ScreenTable {
+    pub(crate) name: String,
+    /// [`DieChoice::describe`] of its die.
+    pub(crate) description: String,
+    /// The converted table.
+    pub(crate) markdown: String,
+}
+
+/// The chapter of `screen-chapter`, with the tagged tables in two columns
+/// for printing. Every table is kept in one column, under its name and how
+/// its die was picked.
+pub(crate) fn screen_chapter(title: &str, tables: &[ScreenTable]) -> Chapter {
+    // Markdown inside HTML blocks is only parsed after a blank line.
+    let mut content = format!(
+        "# {}\n\n<div class=\"rolltables-screen\" style=\"column-count: 2; column-gap: 2em;\">\n\n",
+        title
+    );
+    for table in tables {
+        content.push_str(&format!(
+            "<div style=\"break-inside: avoid;\">\n\n**{}**: {}\n\n{}\n\n</div>\n\n",
+            table.name, table.description, table.markdown
+        ));
+    }
+    content.push_str("</div>\n");
+
+    let mut chapter = Chapter::new(title, content, format!("{}.md", slug(title)), vec![]);
+    // There is no file behind it.
+    chapter.source_path = None;
+    chapter
+}
+
+/// Reports the table of `stats = true`, to stderr by default. With
+/// `detailed` tables whose rows aren't equally likely also get the chances
+/// of the least and most likely one.
+pub(crate) fn print_stats(tables: &[TableInfo], detailed: bool, report: &Report) {
+    let entries: usize = tables.iter().map(|info| info.table.rows.len()).sum();
+    let mut lines = vec![format!(
+        "rolltables: {} roll tables with {} rollable results",
+        tables.len(),
+        entries
+    )];
+    for info in tables {
+        let chances = probabilities(&info.weights);
+        let least = chances.iter().copied().fold(f64::INFINITY, f64::min);
+        let most = chances.iter().copied().fold(0.0, f64::max);
+        let uneven = if detailed && least < most {
+            format!(
+                ", {}–{} per row",
+                format_percent(least),
+                format_percent(most)
+            )
+        } else {
+            String::new()
+        };
+        lines.push(format!(
+            "  {} in {}: {}{}",
+            info.table.name,
+            info.location,
+            info.choice.describe(&info.table.die, &info.weights),
+            uneven
+        ));
+    }
+    report.write(&lines);
+}
+
+/// The chance of rolling every row, given how many faces each of them has.
+pub(crate) fn probabilities(weights: &[usize]) -> Vec<f64> {
+    let total: usize = weights.iter().sum();
+    weights
+        .iter()
+        .map(|&weight| weight as f64 / total.max(1) as f64)
+        .collect()
+}
+
+/// Writes the `manifest` JSON file listing where every converted table is
+/// in the files of `src`. mdBook drops the byte order mark of the files it
+/// loads, so the tables of a file starting with one are 3 bytes further in.
+#[cfg(feature = "mdbook")]
+pub(crate) fn write_manifest(path: &Path, src: &Path, tables: &[TableInfo]) -> Result<()> {
+    let tables: Vec<_> = tables
+        .iter()
+        .map(|info| {
+            let bom = info
+                .source_path
+                .as_ref()
+                .and_then(|source| fs::read(src.join(source)).ok())
+                .filter(|file| file.starts_with("\u{feff}".as_bytes()))
+                .map_or(0, |_| "\u{feff}".len());
+            json!({
+                "name": info.table.name,
+                "path": info.source_path.as_deref().map(display_path),
+                "start": bom + info.range.start,
+                "end": bom + info.range.end,
+                "die": info.table.die,
+                "rows": info.table.rows.len(),
+            })
+        })
+        .collect();
+    let manifest = serde_json::to_string_pretty(&json!({ "tables": tables }))?;
+    fs::write(path, manifest + "\n")
+        .with_context(|| format!("Couldn't write the manifest {}", path.display()))?;
+    Ok(())
+}
+
+/// The `<!-- rolltables-meta: {...} -->` comment of `emit-metadata` with the
+/// name, die and faces of every row of the tables of a chapter, for
+/// preprocessors that run after this one. `--`, `<` and `>` are written as
+/// JSON escapes, so the JSON can't end the comment.
+pub(crate) fn metadata_comment(tables: &[TableInfo]) -> Result<String> {
+    let tables: Vec<_> = tables
+        .iter()
+        .map(|info| {
+            let faces: Vec<_> = info.table.rows.iter().map(|row| &row.faces).collect();
+            json!({
+                "name": info.table.name,
+                "die": info.table.die,
+                "faces": faces,
+            })
+        })
+        .collect();
+    let json = serde_json::to_string(&json!({ "tables": tables }))?
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace("--", "-\\u002d");
+    Ok(format!("<!-- rolltables-meta: {} -->", json))
+}
+
+/// A message for every number of rows that was rolled with more than one
+/// die.
+#[cfg(feature = "mdbook")]
+pub(crate) fn inconsistent_dice(tables: &[TableInfo]) -> Vec<String> {
+    let mut by_rows: BTreeMap<usize, Vec<(&str, Vec<&str>)>> = BTreeMap::new();
+    for info in tables {
+        let dice = by_rows.entry(info.table.rows.len()).or_default();
+        match dice.iter_mut().find(|(die, _)| *die == info.table.die) {
+            Some((_, locations)) => {
+                if !locations.contains(&info.location.as_str()) {
+                    locations.push(&info.location);
+                }
+            }
+            None => dice.push((&info.table.die, vec![&info.location])),
+        }
+    }
+
+    by_rows
+        .into_iter()
+        .filter(|(_, dice)| dice.len() > 1)
+        .map(|(rows, dice)| {
+            let dice: Vec<String> = dice
+                .iter()
+                .map(|(die, locations)| format!("{} ({})", die, locations.join(", ")))
+                .collect();
+            format!("Tables with {} rows use {}", rows, dice.join(" and "))
+        })
+        .collect()
+}
+
+/// Names the tables of a chapter `<chapter-number>-<heading-slug>-<ordinal>`,
+/// e.g. `2.1-encounters-1`. The ordinal counts the tables under headings with
+/// the same slug, so the names only change when the chapter is renumbered or
+/// the tables in it are moved around.
+pub(crate) struct TableNames {
+    chapter: String,
+    ordinals: HashMap<String, usize>,
+}
+
+impl TableNames {
+    pub(crate) fn new(chapter: &Chapter) -> Self {
+        let chapter = match &chapter.number {
+            Some(number) => number
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join("."),
+            // Draft and unnumbered chapters don't have a number.
+            None => slug(&chapter.name),
+        };
+        Self {
+            chapter,
+            ordinals: HashMap::new(),
+        }
+    }
+
+    /// Name of the next table, `heading` being the text of the nearest
+    /// heading above it.
+    pub(crate) fn next(&mut self, heading: &str) -> String {
+        let slug = match slug(heading) {
+            slug if slug.is_empty() => "table".to_string(),
+            slug => slug,
+        };
+        let ordinal = self.ordinals.entry(slug.clone()).or_default();
+        *ordinal += 1;
+        format!("{}-{}-{}", self.chapter, slug, ordinal)
+    }
+}
+
+/// Lowercases the text and replaces everything but letters and digits with
+/// single dashes.
+fn slug(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(fold_case)
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -0,0 +1,192 @@
+use crate::{
+    parser_options,
+    table::{cell_text, plain_text, MarkdownTable},
+    Config, Formula,
+};
+use anyhow::{anyhow, Error, Result};
+use pulldown_cmark::{Alignment, Event, Parser, Tag};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// A converted roll table as plain data, independent of markdown and HTML.
+/// Cells are kept as markdown, e.g. `**Ogre** (2)`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollTable {
+    /// Unique and stable name, e.g. `2.1-encounters-1` for the first table
+    /// under "Encounters" in chapter 2.1.
+    pub name: String,
+    /// The header of the die column, e.g. `d66`.
+    pub die: String,
+    /// The dice expression the table is rolled with, e.g. `1d6*10+1d6` for
+    /// a d66, see [`Formula`]. `None` for custom dice, `dxd` tables and
+    /// faces that can't be read as numbers.
+    #[serde(default)]
+    pub formula: Option<String>,
+    /// The headers of the other columns.
+    pub columns: Vec<String>,
+    /// One row per result.
+    pub rows: Vec<RollRow>,
+}
+
+/// A result of a [`RollTable`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RollRow {
+    /// The faces as written in the die column, e.g. `1`, `2` or `3–5`.
+    pub faces: Vec<String>,
+    /// The results of the [`RollTable::formula`] each face stands for, empty
+    /// without one.
+    #[serde(default)]
+    pub bounds: Vec<FaceBounds>,
+    /// The other cells, as markdown.
+    pub cells: Vec<String>,
+}
+
+/// The lowest and highest result of the formula a face of a [`RollRow`]
+/// stands for, e.g. 3 and 5 for `3–5`, or 34 and 34 for `34` of a d66.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FaceBounds {
+    /// The lowest result.
+    pub low: i64,
+    /// The highest result.
+    pub high: i64,
+}
+
+impl RollTable {
+    /// Writes the table as a markdown table with the faces in the first
+    /// column, see [`MarkdownTable::from`].
+    pub fn to_markdown(&self) -> String {
+        MarkdownTable::from(self).to_markdown("", false)
+    }
+
+    /// Reads a converted table like [`RollTable::try_from`], with its name,
+    /// die and formula.
+    pub(crate) fn from_markdown(
+        name: String,
+        die: String,
+        formula: Option<Formula>,
+        table: &MarkdownTable,
+        config: &Config,
+    ) -> Self {
+        Self {
+            name,
+            die,
+            ..Self::try_from(table).expect("a parsed table has a column")
+        }
+        .with_formula(formula, config)
+    }
+
+    /// Sets the formula and the bounds of every face, unless one of the faces
+    /// can't be read.
+    pub(crate) fn with_formula(mut self, formula: Option<Formula>, config: &Config) -> Self {
+        let formula = match formula {
+            Some(formula) => formula,
+            None => return self,
+        };
+        let range_separator = config.range_separator.as_deref().unwrap_or("–");
+        let mut bounds = vec![];
+        for row in &self.rows {
+            let row_bounds: Option<Vec<FaceBounds>> = row
+                .faces
+                .iter()
+                .map(|face| {
+                    let (low, high) = formula.bounds(face, range_separator, &config.separator)?;
+                    Some(FaceBounds { low, high })
+                })
+                .collect();
+            match row_bounds {
+                Some(row_bounds) => bounds.push(row_bounds),
+                None => return self,
+            }
+        }
+
+        self.formula = Some(formula.to_string());
+        for (row, bounds) in self.rows.iter_mut().zip(bounds) {
+            row.bounds = bounds;
+        }
+        self
+    }
+}
+
+impl TryFrom<&MarkdownTable<'_>> for RollTable {
+    type Error = Error;
+
+    /// Reads a converted table, the faces being the comma separated parts of
+    /// its first column and the die the text of its header. The name is
+    /// empty and there is no formula, as a table doesn't have them. Fails
+    /// for a table without columns.
+    fn try_from(table: &MarkdownTable) -> Result<Self> {
+        let (die, columns) = table
+            .head()
+            .split_first()
+            .ok_or_else(|| anyhow!("a table without columns has no die column"))?;
+        Ok(Self {
+            name: String::new(),
+            die: plain_text(die).trim().to_string(),
+            formula: None,
+            columns: columns
+                .iter()
+                .map(|cell| table.cell_to_markdown(cell))
+                .collect(),
+            rows: table
+                .rows()
+                .iter()
+                .map(|row| RollRow {
+                    faces: faces(&row[0]),
+                    bounds: vec![],
+                    cells: row[1..]
+                        .iter()
+                        .map(|cell| table.cell_to_markdown(cell))
+                        .collect(),
+                })
+                .collect(),
+        })
+    }
+}
+
+impl<'a> From<&'a RollTable> for MarkdownTable<'a> {
+    /// A table with the die in the first header and the faces of every row,
+    /// joined with commas, in the first column. The other cells are parsed
+    /// as markdown, and no column is aligned.
+    fn from(table: &'a RollTable) -> Self {
+        let head = Some(markdown_events(&table.die))
+            .into_iter()
+            .chain(table.columns.iter().map(|cell| markdown_events(cell)))
+            .collect();
+        // The faces are plain text, the other cells markdown.
+        let rows = table.rows.iter().map(|row| {
+            Some(vec![Event::Text(row.faces.join(", ").into())])
+                .into_iter()
+                .chain(row.cells.iter().map(|cell| markdown_events(cell)))
+                .collect()
+        });
+        let mut content: Vec<Vec<_>> = Some(head).into_iter().chain(rows).collect();
+        // Rows with fewer cells than the others get empty ones.
+        let columns = content.iter().map(Vec::len).max().unwrap_or(0);
+        for row in &mut content {
+            row.resize(columns, vec![]);
+        }
+        MarkdownTable::from_content(vec![Alignment::None; columns], content, None)
+    }
+}
+
+/// The faces in a die cell, e.g. `1, 2,<br>3` has three.
+pub(crate) fn faces(cell: &[Event]) -> Vec<String> {
+    cell_text(cell)
+        .split(',')
+        .map(str::trim)
+        .filter(|face| !face.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// The inline events of a markdown cell, without the paragraph around them.
+pub(crate) fn markdown_events(cell: &str) -> Vec<Event<'_>> {
+    Parser::new_ext(cell, parser_options())
+        .filter(|ev| {
+            !matches!(
+                ev,
+                Event::Start(Tag::Paragraph) | Event::End(Tag::Paragraph)
+            )
+        })
+        .collect()
+}
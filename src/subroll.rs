@@ -0,0 +1,83 @@
+use pulldown_cmark::Event;
+
+/// Numbers the `<br>` separated lines of a result cell that start with
+/// `d:` as the faces of a smaller roll, one face per line. E.g.
+/// `Treasure:<br>d: gold<br>d: gems` becomes
+/// `Treasure (d2):<br>1) gold<br>2) gems`. Returns false and leaves the cell
+/// as it is unless every line but the first one is such a line and there
+/// are 2 to 6 of them, so lists written by hand are left alone.
+pub(crate) fn number_sub_rolls(cell: &mut Vec<Event>) -> bool {
+    let mut lines = vec![vec![]];
+    let mut breaks = vec![];
+    for ev in cell.drain(..) {
+        if is_break(&ev) {
+            breaks.push(ev);
+            lines.push(vec![]);
+        } else {
+            lines.last_mut().unwrap().push(ev);
+        }
+    }
+
+    let lead = if item_text(&lines[0]).is_some() { 0 } else { 1 };
+    let items = lines.len() - lead;
+    if !(2..=6).contains(&items) || lines[lead..].iter().any(|line| item_text(line).is_none()) {
+        // Puts the cell back together.
+        let mut breaks = breaks.into_iter();
+        for (i, line) in lines.into_iter().enumerate() {
+            if i > 0 {
+                cell.extend(breaks.next());
+            }
+            cell.extend(line);
+        }
+        return false;
+    }
+
+    let mut lines = lines.into_iter();
+    if lead == 1 {
+        // The die goes before a colon, "Treasure (d3):".
+        let mut line = lines.next().unwrap();
+        let colon = match line.last_mut() {
+            Some(Event::Text(text)) if text.trim_end().ends_with(':') => {
+                *text = text.trim_end().trim_end_matches(':').to_string().into();
+                ":"
+            }
+            _ => "",
+        };
+        cell.extend(line);
+        cell.push(Event::Text(format!(" (d{}){}", items, colon).into()));
+    } else {
+        cell.push(Event::Text(format!("d{}", items).into()));
+        breaks.insert(0, Event::Html("<br>".into()));
+    }
+
+    for ((i, line), br) in lines.enumerate().zip(breaks) {
+        let mut line = line.into_iter();
+        let text = match line.next() {
+            Some(Event::Text(text)) => text,
+            _ => unreachable!("checked by item_text"),
+        };
+        let rest = text.trim_start()[2..].trim_start();
+        cell.push(br);
+        cell.push(Event::Text(format!("{}) {}", i + 1, rest).into()));
+        cell.extend(line);
+    }
+    true
+}
+
+/// The text of a line starting with `d:`.
+fn item_text<'a>(line: &'a [Event]) -> Option<&'a str> {
+    match line.first() {
+        Some(Event::Text(text)) if text.trim_start().starts_with("d:") => Some(text),
+        _ => None,
+    }
+}
+
+fn is_break(ev: &Event) -> bool {
+    match ev {
+        Event::Html(html) => {
+            let tag: String = html.chars().filter(|c| !c.is_whitespace()).collect();
+            matches!(tag.to_ascii_lowercase().as_str(), "<br>" | "<br/>")
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,893 @@
+use crate::text::fold_case;
+use anyhow::{anyhow, Result};
+use pulldown_cmark::{Alignment, Event, LinkType, Tag};
+use std::ops::Range;
+use unicode_width::UnicodeWidthStr;
+
+/// A markdown table as pulldown-cmark events, the cells of the head first.
+///
+/// Every row has as many cells as the head and there is one alignment per
+/// column, the methods that change the table keep it that way. The die
+/// column is the first one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkdownTable<'a> {
+    pub(crate) alignment: Vec<Alignment>,
+    pub(crate) content: Vec<Vec<Vec<Event<'a>>>>,
+    /// Every parsed cell along with its source. Cells that are left as they
+    /// were are written back from the source, so their escapes, math etc.
+    /// stay byte-identical.
+    sources: Vec<(Vec<Event<'a>>, &'a str)>,
+    /// False if the events ended before `End(Table)`, e.g. in a truncated
+    /// `{{#include}}` fragment.
+    pub(crate) complete: bool,
+    /// False if the head was written without the leading pipe, e.g.
+    /// `d | Class`. Converted tables keep that style.
+    outer_pipes: bool,
+    /// Rows after the head that [`Self::rows`] leaves out, set by a
+    /// `skip-rows` directive. They are still written as they are.
+    skipped: usize,
+    /// How many faces every row after the head gets, if they aren't all
+    /// equally likely, e.g. in a `rolltable` block.
+    weights: Option<Vec<usize>>,
+}
+
+impl<'a> MarkdownTable<'a> {
+    pub(crate) fn new(
+        alignment: Vec<Alignment>,
+        parser: &mut impl Iterator<Item = (Event<'a>, Range<usize>)>,
+        source: &'a str,
+    ) -> Self {
+        let mut content = vec![];
+        let mut sources = vec![];
+
+        let mut in_head = false;
+        let mut outer_pipes = true;
+        let mut row_source = "";
+
+        let complete = loop {
+            match parser.next() {
+                Some((Event::Start(Tag::TableHead), range)) => {
+                    in_head = true;
+                    outer_pipes = starts_with_pipe(source.get(range).unwrap_or_default());
+                    content.push(vec![]);
+                }
+                Some((Event::End(Tag::TableHead), _)) => in_head = false,
+                // Newer versions of pulldown-cmark also wrap the head cells
+                // in a `TableRow`, older ones don't.
+                Some((Event::Start(Tag::TableRow), range)) if !in_head => {
+                    row_source = source.get(range).unwrap_or_default();
+                    content.push(vec![]);
+                }
+                Some((Event::Start(Tag::TableCell), _)) => content.last_mut().unwrap().push(vec![]),
+                Some((Event::End(Tag::TableCell), range)) => {
+                    let cell = content.last().unwrap().last().unwrap();
+                    sources.push((cell.clone(), source.get(range).unwrap_or_default()));
+                }
+                // In a table without outer pipes `  | Fighter` is a row
+                // starting with "Fighter", but it's clearly meant to have an
+                // empty first cell.
+                Some((Event::End(Tag::TableRow), _)) if !in_head => {
+                    let row = content.last_mut().unwrap();
+                    if !outer_pipes
+                        && starts_with_pipe(row_source)
+                        && row.last().is_some_and(Vec::is_empty)
+                    {
+                        row.rotate_right(1);
+                    }
+                }
+                Some((Event::Start(Tag::TableRow) | Event::End(Tag::TableRow), _)) => {}
+                Some((Event::End(Tag::Table(_)), _)) => break true,
+                Some((ev, _)) => content.last_mut().unwrap().last_mut().unwrap().push(ev),
+                None => break false,
+            }
+        };
+
+        // The delimiter row decides how many columns there are. Parsers
+        // have differed on rows with more or fewer cells, so every row is
+        // made as long, even if the parser already did.
+        for row in &mut content {
+            row.resize(alignment.len(), vec![]);
+        }
+
+        Self {
+            alignment,
+            content,
+            sources,
+            complete,
+            outer_pipes,
+            skipped: 0,
+            weights: None,
+        }
+    }
+
+    /// A table that wasn't parsed from a chapter, with the head as the
+    /// first row of `content`.
+    pub(crate) fn from_content(
+        alignment: Vec<Alignment>,
+        content: Vec<Vec<Vec<Event<'a>>>>,
+        weights: Option<Vec<usize>>,
+    ) -> Self {
+        Self {
+            alignment,
+            content,
+            sources: vec![],
+            complete: true,
+            outer_pipes: true,
+            skipped: 0,
+            weights,
+        }
+    }
+
+    /// How many faces each of [`Self::rows`] gets, `None` if one each.
+    pub(crate) fn weights(&self) -> Option<&[usize]> {
+        self.weights
+            .as_ref()
+            .map(|weights| &weights[self.skipped..])
+    }
+
+    /// The cells of the head.
+    pub fn head(&self) -> &[Vec<Event<'a>>] {
+        &self.content[0][..]
+    }
+
+    /// The cells of the head, to be changed in place.
+    pub fn head_mut(&mut self) -> &mut [Vec<Event<'a>>] {
+        &mut self.content[0][..]
+    }
+
+    /// The rows below the head, without the ones left out by `skip-rows`.
+    pub fn rows(&self) -> &[Vec<Vec<Event<'a>>>] {
+        &self.content[1 + self.skipped..]
+    }
+
+    /// The rows below the head, to be changed in place. Cells can be
+    /// replaced but not added or removed, see [`Self::insert_column`].
+    pub fn rows_mut(&mut self) -> &mut [Vec<Vec<Event<'a>>>] {
+        &mut self.content[1 + self.skipped..]
+    }
+
+    /// The alignment of every column.
+    pub fn alignment(&self) -> &[Alignment] {
+        &self.alignment
+    }
+
+    /// Inserts a column before the column `index`, or at the end if it is
+    /// the number of columns. `cells` has one cell for each of
+    /// [`Self::rows`], rows left out by `skip-rows` get an empty cell.
+    pub fn insert_column(
+        &mut self,
+        index: usize,
+        head: Vec<Event<'a>>,
+        cells: Vec<Vec<Event<'a>>>,
+        alignment: Alignment,
+    ) -> Result<()> {
+        if index > self.alignment.len() {
+            return Err(anyhow!(
+                "column {} of a table with {} columns",
+                index,
+                self.alignment.len()
+            ));
+        }
+        if cells.len() != self.rows().len() {
+            return Err(anyhow!(
+                "{} cells for a table with {} rows",
+                cells.len(),
+                self.rows().len()
+            ));
+        }
+
+        self.alignment.insert(index, alignment);
+        self.content[0].insert(index, head);
+        for row in &mut self.content[1..1 + self.skipped] {
+            row.insert(index, vec![]);
+        }
+        for (row, cell) in self.content[1 + self.skipped..].iter_mut().zip(cells) {
+            row.insert(index, cell);
+        }
+        Ok(())
+    }
+
+    /// Removes the column `index` from the head and every row.
+    pub fn remove_column(&mut self, index: usize) -> Result<()> {
+        if index >= self.alignment.len() {
+            return Err(anyhow!(
+                "column {} of a table with {} columns",
+                index,
+                self.alignment.len()
+            ));
+        }
+
+        self.alignment.remove(index);
+        for row in &mut self.content {
+            row.remove(index);
+        }
+        Ok(())
+    }
+
+    /// Adds a column at the end, see [`Self::insert_column`].
+    pub fn push_column(
+        &mut self,
+        alignment: Alignment,
+        head: Vec<Event<'a>>,
+        cells: Vec<Vec<Event<'a>>>,
+    ) -> Result<()> {
+        self.insert_column(self.alignment.len(), head, cells, alignment)
+    }
+
+    /// Adds an empty first column with a "d" header, for `add-die-column`.
+    pub(crate) fn insert_die_column(&mut self, alignment: Alignment) {
+        let cells = vec![vec![]; self.rows().len()];
+        self.insert_column(0, vec![Event::Text("d".into())], cells, alignment)
+            .expect("one cell for every row");
+    }
+
+    /// Undoes [`Self::insert_die_column`].
+    pub(crate) fn remove_die_column(&mut self) {
+        self.remove_column(0).expect("the die column was inserted");
+    }
+
+    /// Sorts [`Self::rows`] by the text of `column` (see [`cell_text`]),
+    /// keeping the order of rows with the same text. Rows without the column
+    /// come first.
+    pub(crate) fn sort_rows(&mut self, column: usize, descending: bool, ignore_case: bool) {
+        let key = |row: &Vec<Vec<Event>>| {
+            let text = row
+                .get(column)
+                .map(|cell| cell_text(cell))
+                .unwrap_or_default();
+            if ignore_case {
+                fold_case(&text)
+            } else {
+                text
+            }
+        };
+        let rows = self.rows();
+        let mut order: Vec<usize> = (0..rows.len()).collect();
+        order.sort_by(|&a, &b| {
+            let order = key(&rows[a]).cmp(&key(&rows[b]));
+            if descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+        self.reorder_rows(&order);
+    }
+
+    /// Shuffles the rows with a Fisher–Yates shuffle driven by SplitMix64,
+    /// so the same seed always gives the same order on every platform.
+    pub(crate) fn shuffle_rows(&mut self, seed: u64) {
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        let mut order: Vec<usize> = (0..self.rows().len()).collect();
+        for i in (1..order.len()).rev() {
+            order.swap(i, (next() % (i as u64 + 1)) as usize);
+        }
+        self.reorder_rows(&order);
+    }
+
+    /// Breaks the cells of `column` in [`Self::rows`] with `<br>` between
+    /// words, see [`wrap_cell`]. Returns whether any of them changed. Cells
+    /// with math like `$x$` or `\(x\)` are left as they are, written again
+    /// it would be escaped and broken up before mdbook-katex reads it.
+    pub(crate) fn wrap_column(&mut self, column: usize, width: usize) -> bool {
+        let mut changed = false;
+        for i in 0..self.rows().len() {
+            let wrapped = match self.rows()[i].get(column) {
+                Some(cell) if !has_math(&self.cell_to_markdown(cell)) => wrap_cell(cell, width),
+                _ => None,
+            };
+            if let Some(wrapped) = wrapped {
+                self.rows_mut()[i][column] = wrapped;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Puts the row at `order[i]` at `i`, with its weight.
+    fn reorder_rows(&mut self, order: &[usize]) {
+        let rows = self.rows();
+        let reordered: Vec<_> = order.iter().map(|&i| rows[i].clone()).collect();
+        self.rows_mut().clone_from_slice(&reordered);
+        let skipped = self.skipped;
+        if let Some(weights) = &mut self.weights {
+            let reordered: Vec<_> = order.iter().map(|&i| weights[skipped + i]).collect();
+            weights[skipped..].copy_from_slice(&reordered);
+        }
+    }
+
+    /// Keeps only the [`Self::rows`] with the given indexes.
+    pub(crate) fn retain_rows(&mut self, indexes: &[usize]) {
+        let first = 1 + self.skipped;
+        let mut i = 0;
+        self.content.retain(|_| {
+            i += 1;
+            i - 1 < first || indexes.contains(&(i - 1 - first))
+        });
+        if let Some(weights) = &mut self.weights {
+            let mut i = 0;
+            weights.retain(|_| {
+                i += 1;
+                i - 1 < first - 1 || indexes.contains(&(i - first))
+            });
+        }
+    }
+
+    /// Leaves the first `n` rows out of [`Self::rows`], at most all of them.
+    pub(crate) fn skip_rows(&mut self, n: usize) {
+        self.skipped = n.min(self.content.len() - 1);
+    }
+
+    /// Serializes the table, putting `indent` before every line but the
+    /// first, so tables nested in lists and block quotes stay there. With
+    /// `pretty` the cells are padded so the pipes line up.
+    pub(crate) fn to_markdown(&self, indent: &str, pretty: bool) -> String {
+        let rows: Vec<Vec<String>> = self
+            .content
+            .iter()
+            .map(|row| row.iter().map(|cell| self.cell_to_markdown(cell)).collect())
+            .collect();
+
+        let widths: Vec<usize> = (0..self.alignment.len())
+            .map(|column| {
+                let widest = rows
+                    .iter()
+                    .filter_map(|row| row.get(column))
+                    .map(|cell| cell.width())
+                    .max()
+                    .unwrap_or_default();
+                widest.max(3)
+            })
+            .collect();
+
+        let delimiters: Vec<String> = self
+            .alignment
+            .iter()
+            .zip(&widths)
+            .map(|(alignment, width)| {
+                let (left, right) = match alignment {
+                    Alignment::None => ("", ""),
+                    Alignment::Left => (":", ""),
+                    Alignment::Center => (":", ":"),
+                    Alignment::Right => ("", ":"),
+                };
+                let dashes = if pretty {
+                    width - left.len() - right.len()
+                } else {
+                    3
+                };
+                format!("{}{}{}", left, "-".repeat(dashes), right)
+            })
+            .collect();
+
+        // Without the outer pipes an empty cell at either end would be lost.
+        let outer_pipes = self.outer_pipes
+            || rows.iter().any(|row| {
+                row.first().is_none_or(String::is_empty) || row.last().is_none_or(String::is_empty)
+            });
+
+        let write_row = |row: &[String]| {
+            let inner = if pretty {
+                let cells: Vec<_> = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| {
+                        format!(
+                            " {}{} ",
+                            cell,
+                            " ".repeat(width.saturating_sub(cell.width()))
+                        )
+                    })
+                    .collect();
+                cells.join("|")
+            } else {
+                row.join("|")
+            };
+
+            if outer_pipes {
+                format!("|{}|", inner)
+            } else {
+                inner.trim().to_string()
+            }
+        };
+
+        let mut lines = vec![write_row(&rows[0])];
+        lines.push(write_row(&delimiters));
+        lines.extend(rows[1..].iter().map(|row| write_row(row)));
+
+        lines.join(&format!("\n{}", indent))
+    }
+
+    /// The markdown of a cell, as written if it wasn't changed.
+    pub(crate) fn cell_to_markdown(&self, cell: &[Event<'a>]) -> String {
+        match self.sources.iter().find(|(events, _)| events == cell) {
+            Some((_, source)) => source.to_string(),
+            None => inline_to_markdown(cell),
+        }
+    }
+}
+
+/// Whether `markdown` has a math delimiter of mdbook-katex.
+fn has_math(markdown: &str) -> bool {
+    markdown.contains('$') || markdown.contains("\\(") || markdown.contains("\\[")
+}
+
+/// `cell` with a `<br>` before each word that would make its line longer
+/// than `width`, or `None` if nothing has to be broken. Only text outside
+/// of formatting is broken, so `**a few words**` stays on one line, and
+/// cells with code spans, links or images are left as they are. Breaks
+/// already in the cell start a new line, so wrapping again changes
+/// nothing.
+fn wrap_cell<'a>(cell: &[Event<'a>], width: usize) -> Option<Vec<Event<'a>>> {
+    let awkward = cell.iter().any(|ev| {
+        matches!(
+            ev,
+            Event::Code(_) | Event::Start(Tag::Link(..)) | Event::Start(Tag::Image(..))
+        )
+    });
+    if awkward {
+        return None;
+    }
+
+    let mut wrapped = vec![];
+    let mut line = 0;
+    let mut depth = 0usize;
+    let mut changed = false;
+    for (i, ev) in cell.iter().enumerate() {
+        match ev {
+            Event::Text(text) if depth == 0 => {
+                let mut current = String::new();
+                for (i, word) in text.split(' ').enumerate() {
+                    let word_width = word.width();
+                    if i > 0 && line > 0 && word_width > 0 && line + 1 + word_width > width {
+                        if !current.is_empty() {
+                            wrapped.push(Event::Text(std::mem::take(&mut current).into()));
+                        }
+                        wrapped.push(Event::Html("<br>".into()));
+                        line = 0;
+                        changed = true;
+                    } else if i > 0 {
+                        current.push(' ');
+                        line += 1;
+                    }
+                    current.push_str(word);
+                    line += word_width;
+                }
+                if !current.is_empty() {
+                    wrapped.push(Event::Text(current.into()));
+                }
+                continue;
+            }
+            Event::Text(text) => line += text.width(),
+            Event::HardBreak => line = 0,
+            Event::Html(html) if is_line_break(html) => line = 0,
+            Event::Start(_) => {
+                // Formatted words go to the next line as a whole, if a
+                // space comes before them.
+                let span = if depth == 0 {
+                    formatted_width(&cell[i..])
+                } else {
+                    0
+                };
+                let space = match wrapped.last_mut() {
+                    Some(Event::Text(text)) if text.ends_with(' ') => Some(text),
+                    _ => None,
+                };
+                if let Some(text) = space.filter(|_| line > 1 && line + span > width) {
+                    *text = text.trim_end_matches(' ').to_string().into();
+                    wrapped.push(Event::Html("<br>".into()));
+                    line = 0;
+                    changed = true;
+                }
+                depth += 1;
+            }
+            Event::End(_) => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        wrapped.push(ev.clone());
+    }
+
+    if changed {
+        Some(wrapped)
+    } else {
+        None
+    }
+}
+
+/// The width of the text from the `Start` event `events` begins with to its
+/// `End`.
+fn formatted_width(events: &[Event]) -> usize {
+    let mut depth = 0;
+    let mut width = 0;
+    for ev in events {
+        match ev {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            Event::Text(text) => width += text.width(),
+            _ => {}
+        }
+        if depth == 0 {
+            break;
+        }
+    }
+    width
+}
+
+/// Whether `html` is a `<br>`, written in any of the usual ways.
+fn is_line_break(html: &str) -> bool {
+    let html = html.trim().to_ascii_lowercase();
+    matches!(html.as_str(), "<br>" | "<br/>" | "<br />")
+}
+
+fn starts_with_pipe(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+/// The text of a cell without formatting, used to match markers. Code spans
+/// and text inside inline HTML elements like `<sup>*</sup>` don't count, so
+/// they can be used to decorate the marker. Line breaks and runs of
+/// whitespace, e.g. from a hard-wrapped `&#10;`, count as one space.
+pub(crate) fn plain_text(cell: &[Event]) -> String {
+    let mut text = String::new();
+    for (ev, html_depth) in with_html_depth(cell) {
+        match (ev, html_depth) {
+            (Event::Text(t), 0) => text.push_str(t),
+            (Event::SoftBreak | Event::HardBreak, 0) => text.push(' '),
+            _ => {}
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The text of a header cell without an annotation in parentheses after
+/// the marker, e.g. "d" for "d (hours)".
+pub(crate) fn strip_annotation(text: &str) -> &str {
+    match text.find('(') {
+        Some(start) if start > 0 && text.ends_with(')') => text[..start].trim_end(),
+        _ => text,
+    }
+}
+
+/// Splits a header cell like `d (on 1–2 **check twice**)` into the marker
+/// and the annotation after it, which keeps its formatting, see
+/// [`strip_annotation`].
+pub(crate) fn split_annotation<'a>(cell: &[Event<'a>]) -> Option<(Vec<Event<'a>>, Vec<Event<'a>>)> {
+    let text = plain_text(cell);
+    if strip_annotation(&text).len() == text.len() {
+        return None;
+    }
+
+    let (i, start) = with_html_depth(cell)
+        .enumerate()
+        .find_map(|(i, (ev, html_depth))| match (ev, html_depth) {
+            (Event::Text(t), 0) => t.find('(').map(|start| (i, start)),
+            _ => None,
+        })?;
+    let (before, after) = match &cell[i] {
+        Event::Text(t) => t.split_at(start),
+        _ => unreachable!(),
+    };
+    let marker_end = before.trim_end().len();
+
+    let mut marker = cell[..i].to_vec();
+    if marker_end > 0 {
+        marker.push(Event::Text(before[..marker_end].to_string().into()));
+    }
+    let mut annotation = vec![Event::Text(
+        format!("{}{}", &before[marker_end..], after).into(),
+    )];
+    annotation.extend(cell[i + 1..].iter().cloned());
+    Some((marker, annotation))
+}
+
+/// For a paragraph whose first two lines look like the head and delimiter
+/// row of a table but have different numbers of cells, which parsers don't
+/// read as a table, the first cell of the head and both counts.
+pub(crate) fn mismatched_head(paragraph: &str) -> Option<(String, usize, usize)> {
+    let mut lines = paragraph
+        .lines()
+        .map(|line| line.trim_start_matches(|c: char| c == '>' || c.is_whitespace()));
+    let (head, delimiter) = (lines.next()?, lines.next()?);
+    if !head.contains('|') || !is_delimiter_row(delimiter) {
+        return None;
+    }
+
+    let head = split_row(head);
+    let delimiters = split_row(delimiter).len();
+    if head.len() == delimiters {
+        return None;
+    }
+    Some((head[0].clone(), head.len(), delimiters))
+}
+
+/// For the source of a table, the byte offsets of the rows below its
+/// delimiter row that are followed by another delimiter row with as many
+/// cells, and their first cell. Without a blank line between them, parsers
+/// read a table right below another one as more rows of it.
+pub(crate) fn stacked_heads(table: &str) -> Vec<(usize, String)> {
+    let mut lines = vec![];
+    let mut offset = 0;
+    for line in table.split_inclusive('\n') {
+        let row = line.trim_start_matches(|c: char| c == '>' || c.is_whitespace());
+        lines.push((offset, row));
+        offset += line.len();
+    }
+
+    let mut heads = vec![];
+    for i in 2..lines.len().saturating_sub(1) {
+        let (offset, head) = lines[i];
+        if let Some(marker) = table_head(head, lines[i + 1].1) {
+            heads.push((offset, marker));
+        }
+    }
+    heads
+}
+
+/// The first cell of `head` if it and `delimiter` are the head and
+/// delimiter row of a table, with as many cells.
+pub(crate) fn table_head(head: &str, delimiter: &str) -> Option<String> {
+    let head = head.trim_start_matches(|c: char| c == '>' || c.is_whitespace());
+    let delimiter = delimiter.trim_start_matches(|c: char| c == '>' || c.is_whitespace());
+    if !head.contains('|') || !is_delimiter_row(delimiter) || is_delimiter_row(head) {
+        return None;
+    }
+    let head = split_row(head);
+    if head.len() != split_row(delimiter).len() {
+        return None;
+    }
+    head.into_iter().next()
+}
+
+fn is_delimiter_row(line: &str) -> bool {
+    line.contains('|')
+        && line.contains('-')
+        && line
+            .chars()
+            .all(|c| matches!(c, '|' | ':' | '-' | ' ' | '\t' | '\r' | '\n'))
+}
+
+/// The trimmed cells of a table row, split on pipes that aren't escaped.
+fn split_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = match line.strip_suffix('|') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => line,
+    };
+
+    let mut cells = vec![String::new()];
+    let mut escaped = false;
+    for c in line.chars() {
+        match c {
+            '|' if !escaped => cells.push(String::new()),
+            c => cells.last_mut().unwrap().push(c),
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    cells.iter().map(|cell| cell.trim().to_string()).collect()
+}
+
+/// Whether a cell has nothing in it. The parser may split its text into
+/// several events, so empty text and soft breaks count as nothing too.
+pub(crate) fn is_blank(cell: &[Event]) -> bool {
+    cell.iter().all(|ev| match ev {
+        Event::Text(text) => text.trim().is_empty(),
+        Event::SoftBreak => true,
+        _ => false,
+    })
+}
+
+/// All the text of a cell, including code spans, without formatting.
+pub(crate) fn cell_text(cell: &[Event]) -> String {
+    let mut text = String::new();
+    for ev in cell {
+        if let Event::Text(t) | Event::Code(t) = ev {
+            text.push_str(t);
+        }
+    }
+    text
+}
+
+/// Replaces the [`plain_text`] of a cell, keeping the formatting and
+/// whitespace around it so e.g. `**d**` becomes `**d66**`. Texts that are
+/// only whitespace, like an encoded line break after the marker, are kept
+/// where they are.
+pub(crate) fn replace_plain_text(cell: &mut Vec<Event>, new: String) {
+    let plain: Vec<usize> = with_html_depth(cell)
+        .enumerate()
+        .filter(|(_, (ev, html_depth))| {
+            matches!(ev, Event::Text(t) if !t.trim().is_empty()) && *html_depth == 0
+        })
+        .map(|(i, _)| i)
+        .collect();
+
+    let first = match plain.first() {
+        Some(first) => *first,
+        None => return cell.push(Event::Text(new.into())),
+    };
+
+    let text: String = plain
+        .iter()
+        .map(|i| match &cell[*i] {
+            Event::Text(t) => t.as_ref(),
+            _ => unreachable!(),
+        })
+        .collect();
+    let leading = &text[..text.len() - text.trim_start().len()];
+    let trailing = &text[text.trim_end().len()..];
+    cell[first] = Event::Text(format!("{}{}{}", leading, new, trailing).into());
+
+    for i in plain.into_iter().skip(1).rev() {
+        cell.remove(i);
+    }
+}
+
+/// Pairs every event with the number of inline HTML elements it's inside of.
+fn with_html_depth<'b, 'a>(
+    cell: &'b [Event<'a>],
+) -> impl Iterator<Item = (&'b Event<'a>, usize)> + 'b {
+    let mut depth = 0usize;
+    cell.iter().map(move |ev| {
+        let current = depth;
+        if let Event::Html(html) = ev {
+            let tag = html.trim();
+            let name = tag
+                .trim_start_matches(['<', '/'].as_ref())
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            let void = ["br", "img", "hr", "wbr", "input"].contains(&name.as_str());
+
+            if tag.starts_with("</") {
+                depth = depth.saturating_sub(1);
+                return (ev, depth);
+            } else if tag.starts_with('<')
+                && !tag.starts_with("<!")
+                && !tag.ends_with("/>")
+                && !void
+            {
+                depth += 1;
+            }
+        }
+        (ev, current)
+    })
+}
+
+/// Serializes the inline events of a table cell.
+///
+/// Unlike a general serializer this escapes every `|`, including the ones in
+/// code spans, since even there they would end the cell.
+pub(crate) fn inline_to_markdown(events: &[Event]) -> String {
+    let mut buf = String::new();
+
+    for ev in events {
+        match ev {
+            Event::Text(text) => {
+                let start = if buf.is_empty() {
+                    escape_block_start(text, &mut buf)
+                } else {
+                    0
+                };
+                escape_text(&text[start..], &mut buf)
+            }
+            Event::Code(code) => {
+                // The fence has to be longer than any run of backticks inside.
+                let longest_run = code
+                    .split(|c| c != '`')
+                    .map(str::len)
+                    .max()
+                    .unwrap_or_default();
+                let fence = "`".repeat(longest_run + 1);
+                let padding = if code.starts_with('`') || code.ends_with('`') {
+                    " "
+                } else {
+                    ""
+                };
+                buf.push_str(&fence);
+                buf.push_str(padding);
+                buf.push_str(&code.replace('|', "\\|"));
+                buf.push_str(padding);
+                buf.push_str(&fence);
+            }
+            Event::Html(html) => buf.push_str(html),
+            Event::FootnoteReference(name) => {
+                buf.push_str("[^");
+                buf.push_str(name);
+                buf.push(']');
+            }
+            Event::SoftBreak => buf.push(' '),
+            Event::HardBreak => buf.push_str("<br>"),
+            Event::Start(Tag::Emphasis) | Event::End(Tag::Emphasis) => buf.push('*'),
+            Event::Start(Tag::Strong) | Event::End(Tag::Strong) => buf.push_str("**"),
+            Event::Start(Tag::Strikethrough) | Event::End(Tag::Strikethrough) => buf.push_str("~~"),
+            Event::Start(Tag::Link(LinkType::Autolink | LinkType::Email, ..)) => buf.push('<'),
+            Event::End(Tag::Link(LinkType::Autolink | LinkType::Email, ..)) => buf.push('>'),
+            Event::Start(Tag::Link(..)) => buf.push('['),
+            Event::Start(Tag::Image(..)) => buf.push_str("!["),
+            Event::End(Tag::Link(_, url, title) | Tag::Image(_, url, title)) => {
+                buf.push_str("](");
+                buf.push_str(url);
+                if !title.is_empty() {
+                    buf.push_str(" \"");
+                    buf.push_str(&title.replace('"', "\\\""));
+                    buf.push('"');
+                }
+                buf.push(')');
+            }
+            _ => {}
+        }
+    }
+
+    buf
+}
+
+/// A plain label like a face as cell markdown, e.g. in a template.
+pub(crate) fn escape_label(label: &str) -> String {
+    inline_to_markdown(&[Event::Text(label.into())])
+}
+
+/// Backslash-escapes the first character of a cell that would start a
+/// heading, list or quote if the cell were read on its own, e.g. by a tool
+/// reading an export line by line: `# `, `- `, `+ ` and `1. ` or `1) `.
+/// `*`, `>` and `|` are escaped everywhere anyway. Returns how much of
+/// `text` was pushed.
+fn escape_block_start(text: &str, buf: &mut String) -> usize {
+    let bytes = text.as_bytes();
+    let ends_marker = |i: usize| bytes.get(i).is_none_or(u8::is_ascii_whitespace);
+    let digits = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+
+    let marker = match bytes.first() {
+        Some(b'#') => ends_marker(bytes.iter().take_while(|&&b| b == b'#').count()),
+        Some(b'-') | Some(b'+') => ends_marker(1),
+        Some(b'0'..=b'9') => {
+            digits <= 9
+                && matches!(bytes.get(digits), Some(b'.') | Some(b')'))
+                && ends_marker(digits + 1)
+        }
+        _ => false,
+    };
+    if !marker {
+        return 0;
+    }
+    match bytes[0] {
+        b'#' | b'-' | b'+' => {
+            buf.push('\\');
+            buf.push(bytes[0] as char);
+            1
+        }
+        // The number itself can't be escaped, only what follows it.
+        _ => {
+            buf.push_str(&text[..digits]);
+            buf.push('\\');
+            buf.push(bytes[digits] as char);
+            digits + 1
+        }
+    }
+}
+
+/// Backslash-escapes everything that could start inline markup or end the
+/// cell.
+fn escape_text(text: &str, buf: &mut String) {
+    for c in text.chars() {
+        // A line break in a cell can only have been written as a reference.
+        if c == '\n' {
+            buf.push_str("&#10;");
+            continue;
+        }
+        if matches!(
+            c,
+            '\\' | '|' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '~' | '&'
+        ) {
+            buf.push('\\');
+        }
+        buf.push(c);
+    }
+}
@@ -0,0 +1,15 @@
+// Nothing here reads the locale, so a book comes out the same on every
+// system.
+
+/// The text as compared by the options that ignore case: Unicode's default
+/// lowercase mapping, character by character. Unlike a Turkish collation, "I"
+/// becomes "i" and "İ" becomes "i̇".
+pub(crate) fn fold_case(text: &str) -> String {
+    text.to_lowercase()
+}
+
+/// A chance from 0 to 1 as a percentage with one decimal and a dot, e.g.
+/// `16.7%`, rounded to the nearest tenth.
+pub(crate) fn format_percent(chance: f64) -> String {
+    format!("{:.1}%", chance * 100.0)
+}
@@ -0,0 +1,90 @@
+//! Runs the preprocessor twice over the same book with `cache` set, like two
+//! rebuilds of `mdbook serve`.
+
+use mdbook::book::{Book, BookItem, Chapter};
+use mdbook::preprocess::{Preprocessor, PreprocessorContext};
+use mdbook_rolltables::RollTables;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const TABLE: &str = "|d|Result|\n|---|---|\n||Rain|\n||Sun|\n";
+
+/// Converts a book with a chapter for each of `chapters`, returning their
+/// converted content. Every chapter that isn't taken from the cache adds one
+/// to `converted`.
+fn run(root: &Path, chapters: &[&str], converted: &Arc<AtomicUsize>) -> Vec<String> {
+    let ctx: PreprocessorContext = serde_json::from_value(json!({
+        "root": root,
+        "config": { "preprocessor": { "rolltables": { "cache": "cache" } } },
+        "renderer": "html",
+        "mdbook_version": mdbook::MDBOOK_VERSION,
+    }))
+    .unwrap();
+    let mut book = Book::new();
+    for (i, content) in chapters.iter().enumerate() {
+        let path = format!("{}.md", i);
+        book.push_item(Chapter::new("Tables", content.to_string(), path, vec![]));
+    }
+
+    let converted = Arc::clone(converted);
+    let preprocessor = RollTables::builder()
+        .on_convert(move |_| {
+            converted.fetch_add(1, Ordering::SeqCst);
+        })
+        .build();
+    let book = preprocessor.run(&ctx, book).unwrap();
+    book.iter()
+        .map(|item| match item {
+            BookItem::Chapter(chapter) => chapter.content.clone(),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+fn cache_files(root: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = fs::read_dir(root.join("cache"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn cache() {
+    let root = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cache-book");
+    let _ = fs::remove_dir_all(&root);
+    let count = Arc::new(AtomicUsize::new(0));
+    let run = |chapters: &[&str]| run(&root, chapters, &count);
+    let converted = run(&[TABLE])[0].clone();
+    assert!(converted.contains("|1|Rain|"), "{}", converted);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    // The second run takes the chapter from the cache without converting it.
+    assert_eq!(run(&[TABLE]), [converted.as_str()]);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    let files = cache_files(&root);
+    assert_eq!(files.len(), 1);
+
+    // Files that can't be read are converted and written again.
+    fs::write(&files[0], "{").unwrap();
+    assert_eq!(run(&[TABLE]), [converted.as_str()]);
+    assert_eq!(run(&[TABLE]), [converted.as_str()]);
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+
+    // Changed chapters don't match their old entry, which is replaced.
+    let changed = TABLE.replace("Sun", "Fog");
+    assert!(run(&[&changed])[0].contains("|2|Fog|"));
+    let replaced = cache_files(&root);
+    assert_eq!(replaced.len(), 1);
+    assert_ne!(replaced, files);
+
+    // Chapters that define tables are converted every time.
+    let define = format!("<!-- rolltables: define weather -->\n{}", TABLE);
+    let used = run(&[&define, "<!-- rolltables: use weather -->\n"]);
+    assert!(used[1].contains("|1|Rain|"), "{}", used[1]);
+    assert_eq!(cache_files(&root).len(), 2);
+}
@@ -0,0 +1,89 @@
+//! Runs the command line in process with [`run_cli`], with the arguments,
+//! stdin and stdout of each subcommand.
+
+use mdbook_rolltables::run_cli;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The exit code and stdout of running `args` with `stdin`.
+fn run(args: &[&str], stdin: &str) -> (i32, String) {
+    let mut stdout = vec![];
+    let code = run_cli(args.iter().copied(), stdin.as_bytes(), &mut stdout);
+    (code, String::from_utf8(stdout).unwrap())
+}
+
+/// A book in the target directory named `name`, with a chapter for each of
+/// `chapters`.
+fn book(name: &str, chapters: &[&str]) -> PathBuf {
+    let root = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("book.toml"), "[book]\ntitle = \"Tables\"\n").unwrap();
+    let mut summary = String::from("# Summary\n\n");
+    for (i, chapter) in chapters.iter().enumerate() {
+        summary.push_str(&format!("- [Chapter {}](chapter-{}.md)\n", i, i));
+        fs::write(root.join(format!("src/chapter-{}.md", i)), chapter).unwrap();
+    }
+    fs::write(root.join("src/SUMMARY.md"), summary).unwrap();
+    root
+}
+
+#[test]
+fn supports() {
+    assert_eq!(run(&["supports", "html"], ""), (0, String::new()));
+    assert_eq!(run(&["supports", "epub"], ""), (0, String::new()));
+}
+
+#[test]
+fn process() {
+    let table = "|d|Weather|\n|---|---|\n||Rain|\n||Sun|\n";
+    let (code, stdout) = run(&["process", "--stdin", "--separator", "/"], table);
+    assert_eq!(code, 0);
+    assert_eq!(stdout, "|d2|Weather|\n|---|---|\n|1|Rain|\n|2|Sun|\n");
+
+    // Without --stdin there is nothing to read.
+    assert_eq!(run(&["process"], table).0, 1);
+}
+
+#[test]
+fn help() {
+    for flag in ["-h", "--help"] {
+        let (code, stdout) = run(&[flag], "");
+        assert_eq!(code, 0);
+        assert!(stdout.starts_with("mdbook-rolltables is a preprocessor for mdBook"));
+        for usage in [
+            "supports <renderer>",
+            "process --stdin",
+            "check [--workspace]",
+            "--clear-cache",
+        ] {
+            assert!(stdout.contains(usage), "{}", stdout);
+        }
+    }
+}
+
+/// Anything but mdBook's JSON is an error with exit code 2, without
+/// writing a book.
+#[test]
+fn invalid_input() {
+    for stdin in ["not a book", "", "{}", "[{\"root\": \"/\"}"] {
+        assert_eq!(run(&[], stdin), (2, String::new()), "{}", stdin);
+    }
+}
+
+#[test]
+fn check() {
+    let table = |die: &str| format!("|{}|Loot|\n|---|---|\n||Coins|\n||Gems|\n", die);
+
+    let root = book("cli-consistent", &[&table("d"), &table("d")]);
+    let root = root.to_str().unwrap();
+    assert_eq!(run(&["check", "--root", root], ""), (0, String::new()));
+
+    let root = book("cli-inconsistent", &[&table("d"), &table("d4")]);
+    let (code, stdout) = run(&["check", "--root", root.to_str().unwrap()], "");
+    assert_eq!(code, 1);
+    assert_eq!(
+        stdout,
+        "Tables with 2 rows use d2 (chapter-0.md) and d4 (chapter-1.md)\n"
+    );
+}
@@ -0,0 +1,22 @@
+//! Helpers shared by the tests.
+
+/// The lines that differ, with their line numbers. Line endings and
+/// trailing whitespace are shown escaped, since they are easy to miss.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.split_inclusive('\n').collect();
+    let actual: Vec<&str> = actual.split_inclusive('\n').collect();
+    let mut report = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        let (old, new) = (expected.get(i), actual.get(i));
+        if old == new {
+            continue;
+        }
+        if let Some(old) = old {
+            report.push_str(&format!("{:>4} - {:?}\n", i + 1, old));
+        }
+        if let Some(new) = new {
+            report.push_str(&format!("{:>4} + {:?}\n", i + 1, new));
+        }
+    }
+    report
+}
@@ -0,0 +1,191 @@
+//! Runs another table transformation in the same pass as the conversion.
+
+use mdbook::book::{Book, Chapter};
+use mdbook::BookItem;
+use mdbook_rolltables::pulldown_cmark::{Alignment, Event};
+use mdbook_rolltables::{DieChoice, Formula, RollTables, TableOutcome};
+
+#[test]
+fn page_column() {
+    let markdown =
+        "|d|Class|\n|:---:|:---|\n||Warrior|\n||Thief|\n\n|Not|Rolled|\n|---|---|\n|a|b|\n";
+    let mut book = Book::new();
+    book.push_item(Chapter::new(
+        "Classes",
+        markdown.into(),
+        "classes.md",
+        vec![],
+    ));
+
+    let mut seen = vec![];
+    let book = RollTables::default()
+        .process_with(book, None, |table, outcome| {
+            seen.push(outcome.clone());
+            if let TableOutcome::Converted { .. } = outcome {
+                let pages = (0..table.rows().len())
+                    .map(|i| vec![Event::Text(format!("p. {}", 10 + i).into())])
+                    .collect();
+                table
+                    .push_column(Alignment::Right, vec![Event::Text("Page".into())], pages)
+                    .unwrap();
+            }
+        })
+        .unwrap();
+
+    let content = match &book.sections[0] {
+        BookItem::Chapter(chapter) => chapter.content.clone(),
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        content,
+        "|d2|Class|Page|\n|:---:|:---|---:|\n|1|Warrior|p. 10|\n|2|Thief|p. 11|\n\n\
+         |Not|Rolled|\n|---|---|\n|a|b|\n"
+    );
+    assert_eq!(seen.len(), 2);
+    assert_eq!(seen[1], TableOutcome::Unchanged);
+}
+
+#[test]
+fn transform_skipped_table() {
+    let markdown = "<!-- rolltables: skip -->\n|d|Class|\n|---|---|\n||Warrior|\n||Thief|\n";
+    let mut book = Book::new();
+    book.push_item(Chapter::new(
+        "Classes",
+        markdown.into(),
+        "classes.md",
+        vec![],
+    ));
+
+    // The hook converts the table the directive skipped.
+    let preprocessor = RollTables::default();
+    let config = preprocessor.config(None).unwrap();
+    let mut outcomes = vec![];
+    let book = preprocessor
+        .process_with(book, None, |table, _| {
+            outcomes.push(preprocessor.transform_table(table, &config).unwrap());
+        })
+        .unwrap();
+
+    let content = match &book.sections[0] {
+        BookItem::Chapter(chapter) => chapter.content.clone(),
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        content,
+        "<!-- rolltables: skip -->\n|d2|Class|\n|---|---|\n|1|Warrior|\n|2|Thief|\n"
+    );
+    assert_eq!(
+        outcomes,
+        vec![TableOutcome::Converted {
+            die: "d2".into(),
+            choice: DieChoice::Die(2),
+            weights: vec![1, 1],
+            formula: Some(Formula::Sum {
+                dice: 1,
+                sides: 2,
+                modifier: 0,
+            }),
+        }]
+    );
+}
+
+/// The parser may split a cell into several text events, some empty, and
+/// soft breaks, which still count as a marker or an empty cell.
+#[test]
+fn split_events() {
+    let markdown = "<!-- rolltables: skip -->\n|Die|Class|\n|---|---|\n|a|Warrior|\n|b|Thief|\n";
+    let heads = [
+        (vec![Event::Text("".into()), Event::Text("d".into())], ""),
+        (
+            vec![
+                Event::Text("d".into()),
+                Event::SoftBreak,
+                Event::Text(" ".into()),
+            ],
+            "",
+        ),
+        (
+            vec![Event::SoftBreak, Event::Text("".into())],
+            "assume-empty-header = true\nempty-header-min-rows = 2",
+        ),
+    ];
+
+    for (head, cfg) in heads {
+        let cfg: toml::value::Table = toml::from_str(cfg).unwrap();
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Classes",
+            markdown.into(),
+            "classes.md",
+            vec![],
+        ));
+
+        let preprocessor = RollTables::default();
+        let config = preprocessor.config(Some(&cfg)).unwrap();
+        let mut outcomes = vec![];
+        preprocessor
+            .process_with(book, Some(&cfg), |table, _| {
+                table.head_mut()[0] = head.clone();
+                for row in table.rows_mut() {
+                    row[0] = vec![
+                        Event::Text("".into()),
+                        Event::SoftBreak,
+                        Event::Text("".into()),
+                    ];
+                }
+                outcomes.push(preprocessor.transform_table(table, &config).unwrap());
+            })
+            .unwrap();
+
+        assert!(
+            matches!(&outcomes[..], [TableOutcome::Converted { die, .. }] if die == "d2"),
+            "{:?}: {:?}",
+            head,
+            outcomes
+        );
+    }
+}
+
+#[test]
+fn insert_column() {
+    let markdown = "<!-- rolltables: skip-rows 1 -->\n\
+         |d|Class|\n|---|---|\n|**Group**|**Name**|\n||Warrior|\n||Thief|\n";
+    let mut book = Book::new();
+    book.push_item(Chapter::new(
+        "Classes",
+        markdown.into(),
+        "classes.md",
+        vec![],
+    ));
+
+    let book = RollTables::default()
+        .process_with(book, None, |table, _| {
+            let text = |text: &str| vec![Event::Text(text.to_string().into())];
+            let cells = vec![text("Str"), text("Dex")];
+            assert!(table
+                .insert_column(3, text("Stat"), cells.clone(), Alignment::Center)
+                .is_err());
+            assert!(table
+                .insert_column(1, text("Stat"), cells[..1].to_vec(), Alignment::Center)
+                .is_err());
+            table
+                .insert_column(1, text("Stat"), cells, Alignment::Center)
+                .unwrap();
+            table
+                .insert_column(3, text("Gone"), vec![vec![]; 2], Alignment::None)
+                .unwrap();
+            assert!(table.remove_column(4).is_err());
+            table.remove_column(3).unwrap();
+        })
+        .unwrap();
+
+    let content = match &book.sections[0] {
+        BookItem::Chapter(chapter) => chapter.content.clone(),
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        content,
+        "<!-- rolltables: skip-rows 1 -->\n\
+         |d2|Stat|Class|\n|---|:---:|---|\n|**Group**||**Name**|\n|1|Str|Warrior|\n|2|Dex|Thief|\n"
+    );
+}
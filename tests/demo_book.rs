@@ -0,0 +1,59 @@
+//! Builds `examples/demo-book` with mdBook's HTML renderer and the
+//! preprocessor registered in process, like `mdbook build` would run it.
+
+use mdbook::config::Config;
+use mdbook::MDBook;
+use mdbook_rolltables::RollTables;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn demo_book() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/demo-book");
+    let build_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("demo-book");
+
+    // Loaded with `[preprocessor.rolltables]` mdBook would run the installed
+    // `mdbook-rolltables` instead, so its options are only put back once the
+    // preprocessors are chosen.
+    let mut config = Config::from_disk(root.join("book.toml")).unwrap();
+    let options = config
+        .get_mut("preprocessor")
+        .and_then(|preprocessors| preprocessors.as_table_mut())
+        .and_then(|preprocessors| preprocessors.remove("rolltables"))
+        .unwrap();
+    let mut book = MDBook::load_with_config(&root, config).unwrap();
+    book.config.set("preprocessor.rolltables", options).unwrap();
+    book.config.build.build_dir = build_dir.clone();
+    book.with_preprocessor(RollTables::default());
+    book.build().unwrap();
+
+    // Only the text of the cells is compared, how their alignment is written
+    // depends on the version of mdBook.
+    let html = fs::read_to_string(build_dir.join("tables.html")).unwrap();
+    let cells = cells(&html);
+    for expected in [
+        "d6", "1–2", "d66", "1.1", "6.6", "d8", "7–8", "2d6", "9–11", "d100", "91–00",
+    ] {
+        assert!(
+            cells.iter().any(|cell| cell == expected),
+            "{} isn't a cell of\n{}",
+            expected,
+            html
+        );
+    }
+}
+
+/// The text of every `<th>` and `<td>` in `html`, whatever their attributes.
+fn cells(html: &str) -> Vec<String> {
+    let mut cells = vec![];
+    for (start, _) in html.match_indices('<') {
+        let tag = &html[start..];
+        let is_cell = ["<th", "<td"]
+            .iter()
+            .any(|name| tag.starts_with(name) && matches!(tag.as_bytes()[3], b' ' | b'>'));
+        if let (true, Some(open_end), Some(end)) = (is_cell, tag.find('>'), tag.find("</t")) {
+            cells.push(tag[open_end + 1..end].trim().to_string());
+        }
+    }
+    cells
+}
@@ -0,0 +1,100 @@
+//! The `MDBOOK_ROLLTABLES_*` environment variables come before the options
+//! of the builder, which come before the ones in `book.toml`. The variables
+//! are shared by the whole process, so they are all set in a single test.
+
+use mdbook_rolltables::RollTables;
+use std::env;
+use std::sync::{Arc, Mutex};
+use toml::value::Table;
+
+const TABLE: &str = "|d|Result|\n|---|---|\n||1|\n||2|\n||3|\n||4|\n||5|\n||6|\n||7|\n";
+
+/// Converts a table that is warned about with `strict` and `quiet` set on
+/// the builder if they are `Some` and `cfg` as `book.toml`. Returns the
+/// converted markdown or the error, and what was reported.
+fn run(strict: Option<bool>, quiet: Option<bool>, cfg: &str) -> (Result<String, String>, String) {
+    let mut cfg: Table = toml::from_str(cfg).unwrap();
+    cfg.insert("warn-unusual-dice".into(), true.into());
+
+    let report = Arc::new(Mutex::new(String::new()));
+    let sink = Arc::clone(&report);
+    let mut builder =
+        RollTables::builder().report_to(move |text| sink.lock().unwrap().push_str(text));
+    if let Some(strict) = strict {
+        builder = builder.strict(strict);
+    }
+    if let Some(quiet) = quiet {
+        builder = builder.quiet(quiet);
+    }
+    let converted = builder
+        .build()
+        .process_markdown(TABLE, Some(&cfg))
+        .map_err(|e| e.to_string());
+    let report = report.lock().unwrap().clone();
+    (converted, report)
+}
+
+#[test]
+fn precedence() {
+    let strict = |builder, cfg| run(builder, None, cfg).0.is_err();
+    for var in ["", "0", "1"] {
+        env::set_var("MDBOOK_ROLLTABLES_STRICT", var);
+        for (builder, cfg, unset) in [
+            (None, "", false),
+            (None, "strict = true", true),
+            (Some(false), "strict = true", false),
+            (Some(true), "strict = false", true),
+        ] {
+            let expected = match var {
+                "" => unset,
+                _ => var == "1",
+            };
+            assert_eq!(
+                strict(builder, cfg),
+                expected,
+                "{:?} {:?} {}",
+                var,
+                builder,
+                cfg
+            );
+        }
+    }
+    env::set_var("MDBOOK_ROLLTABLES_STRICT", "yes");
+    assert_eq!(
+        run(None, None, "").0.unwrap_err(),
+        "MDBOOK_ROLLTABLES_STRICT must be 1, 0, true or false"
+    );
+    env::remove_var("MDBOOK_ROLLTABLES_STRICT");
+
+    let quiet = |builder, cfg| run(None, builder, cfg).1.is_empty();
+    for var in ["", "0", "1"] {
+        env::set_var("MDBOOK_ROLLTABLES_QUIET", var);
+        for (builder, cfg, unset) in [
+            (None, "", false),
+            (None, "quiet = true", true),
+            (Some(false), "quiet = true", false),
+            (Some(true), "quiet = false", true),
+        ] {
+            let expected = match var {
+                "" => unset,
+                _ => var == "1",
+            };
+            assert_eq!(
+                quiet(builder, cfg),
+                expected,
+                "{:?} {:?} {}",
+                var,
+                builder,
+                cfg
+            );
+        }
+    }
+    env::remove_var("MDBOOK_ROLLTABLES_QUIET");
+
+    // Disabled, not even a strict book.toml is checked.
+    env::set_var("MDBOOK_ROLLTABLES_DISABLE", "1");
+    assert_eq!(run(Some(true), None, "strict = true").0.unwrap(), TABLE);
+    env::set_var("MDBOOK_ROLLTABLES_DISABLE", "0");
+    assert!(run(None, None, "").0.unwrap().starts_with("|d7|"));
+    env::remove_var("MDBOOK_ROLLTABLES_DISABLE");
+}
@@ -0,0 +1,122 @@
+//! Runs every `tests/fixtures/<name>/input.md` through
+//! [`RollTables::process_markdown`] and compares the result with
+//! `expected.md` byte for byte. The options are the ones of the fixture's
+//! `config.toml` on top of `tests/fixtures/config.toml`, both optional and
+//! written like `[preprocessor.rolltables]`. With `UPDATE_FIXTURES=1` the
+//! results are written to the `expected.md` files instead. Either way every
+//! result has to end with a line break only if its input does, and may only
+//! have lines ending in whitespace that the input has too. The warnings,
+//! one per line with the table they are about, are compared with
+//! `warnings.txt` the same way, a missing file meaning there are none.
+
+mod common;
+
+use common::diff;
+use mdbook_rolltables::{Diagnostic, DiagnosticSink, RollTables};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use toml::value::Table;
+
+/// Keeps the warnings of a fixture as the lines of `warnings.txt`.
+struct Collect(Arc<Mutex<String>>);
+
+impl DiagnosticSink for Collect {
+    fn report(&self, diagnostic: Diagnostic) {
+        let mut warnings = self.0.lock().unwrap();
+        if let Some(table) = diagnostic.table {
+            warnings.push_str(&format!("table {}: ", table));
+        }
+        warnings.push_str(&diagnostic.message);
+        warnings.push('\n');
+    }
+}
+
+#[test]
+fn fixtures() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let update = env::var("UPDATE_FIXTURES").is_ok_and(|v| v == "1");
+    let base = read_config(&root.join("config.toml"));
+
+    let mut dirs: Vec<PathBuf> = fs::read_dir(&root)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    assert!(!dirs.is_empty(), "no fixtures in {}", root.display());
+
+    let mut failures = vec![];
+    for dir in &dirs {
+        let name = dir.file_name().unwrap().to_string_lossy();
+        let mut cfg = base.clone();
+        cfg.extend(read_config(&dir.join("config.toml")));
+
+        let input = fs::read_to_string(dir.join("input.md")).unwrap();
+        let warnings = Arc::new(Mutex::new(String::new()));
+        let preprocessor = RollTables::builder()
+            .with_sink(Collect(Arc::clone(&warnings)))
+            .build();
+        let actual = match preprocessor.process_markdown(&input, Some(&cfg)) {
+            Ok(actual) => actual,
+            Err(e) => {
+                failures.push(format!("{}: failed with {:?}", name, e));
+                continue;
+            }
+        };
+
+        // A diff of lines hides these, so they are checked byte by byte.
+        if actual.ends_with('\n') != input.ends_with('\n') {
+            failures.push(format!("{}: the final line break isn't kept", name));
+        }
+        for (i, line) in actual.lines().enumerate() {
+            if line.ends_with([' ', '\t']) && !input.lines().any(|old| old == line) {
+                failures.push(format!("{}: line {} ends with whitespace", name, i + 1));
+            }
+        }
+
+        let expected_path = dir.join("expected.md");
+        let warnings_path = dir.join("warnings.txt");
+        let warnings = warnings.lock().unwrap().clone();
+        if update {
+            fs::write(&expected_path, &actual).unwrap();
+            if warnings.is_empty() {
+                let _ = fs::remove_file(&warnings_path);
+            } else {
+                fs::write(&warnings_path, &warnings).unwrap();
+            }
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual != expected {
+            failures.push(format!("{}:\n{}", name, diff(&expected, &actual)));
+        }
+        let expected = fs::read_to_string(&warnings_path).unwrap_or_default();
+        if warnings != expected {
+            failures.push(format!(
+                "{} warnings:\n{}",
+                name,
+                diff(&expected, &warnings)
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} fixtures differ, UPDATE_FIXTURES=1 rewrites them:\n\n{}",
+        failures.len(),
+        dirs.len(),
+        failures.join("\n\n")
+    );
+}
+
+/// An empty table if the file doesn't exist.
+fn read_config(path: &Path) -> Table {
+    match fs::read_to_string(path) {
+        Ok(text) => toml::from_str(&text)
+            .unwrap_or_else(|e| panic!("{} isn't valid TOML: {}", path.display(), e)),
+        Err(_) => Table::new(),
+    }
+}
@@ -0,0 +1,194 @@
+//! Checks the formula of exported roll tables against the bounds of their
+//! faces: every result the formula can roll has to be in the bounds of
+//! exactly one face, and every bound has to be a result it can roll.
+
+use mdbook::book::{Book, Chapter};
+use mdbook_rolltables::{RollTable, RollTables};
+use std::collections::BTreeSet;
+use toml::value::Table;
+
+/// The roll tables of a chapter, converted with `cfg` written like
+/// `[preprocessor.rolltables]`.
+fn export(markdown: &str, cfg: &str) -> Vec<RollTable> {
+    let cfg: Table = toml::from_str(cfg).unwrap();
+    let mut book = Book::new();
+    book.push_item(Chapter::new("Tables", markdown.into(), "tables.md", vec![]));
+    RollTables::default().roll_tables(book, Some(&cfg)).unwrap()
+}
+
+/// A table with `rows` rows and `marker` in the header.
+fn table(marker: &str, rows: usize) -> String {
+    let mut markdown = format!("|{}|Result|\n|---|---|\n", marker);
+    for row in 0..rows {
+        markdown.push_str(&format!("||{}|\n", row));
+    }
+    markdown
+}
+
+fn check(table: &RollTable) {
+    let formula = table.formula.as_deref().unwrap();
+    let results = evaluate(formula);
+    for row in &table.rows {
+        assert_eq!(row.bounds.len(), row.faces.len(), "{}", table.die);
+        for bounds in &row.bounds {
+            assert!(
+                results.contains(&bounds.low) && results.contains(&bounds.high),
+                "{} can't roll the bounds of {:?}",
+                formula,
+                row.faces
+            );
+        }
+    }
+    for result in results {
+        let faces: Vec<_> = table
+            .rows
+            .iter()
+            .flat_map(|row| &row.bounds)
+            .filter(|bounds| bounds.low <= result && result <= bounds.high)
+            .collect();
+        assert_eq!(
+            faces.len(),
+            1,
+            "{} of {} is in {:?}",
+            result,
+            formula,
+            faces
+        );
+    }
+}
+
+#[test]
+fn formulas() {
+    let cases = [
+        (table("d", 3), "", "d6", "1d6"),
+        (table("d", 36), "", "d66", "1d6*10+1d6"),
+        (table("d", 24), "separator = \".\"", "d64", "1d6*10+1d4"),
+        (
+            table("dd", 120),
+            "markers = { dd = \"combination\" }\nseparator = \".\"\nhead-separator = \"d\"",
+            "d12d10",
+            "(1d12-1)*10+1d10",
+        ),
+        (
+            table("D", 4),
+            "markers = { D = \"percentile\" }",
+            "d100",
+            "1d100",
+        ),
+        (
+            "|d|Weather|\n|---|---|\n|15%|Rain|\n|15%|Fog|\n|70%|Sun|\n".into(),
+            "",
+            "d100",
+            "1d100",
+        ),
+        (table("2d6!reaction", 5), "", "2d6", "2d6"),
+        (table("1d8+2", 4), "", "1d8+2", "1d8+2"),
+        (table("d20-1", 20), "", "d20-1", "1d20-1"),
+        (
+            format!("<!-- rolltables: reserve-top=2 -->\n{}", table("d", 5)),
+            "",
+            "d6",
+            "1d6",
+        ),
+        (
+            format!("<!-- rolltables: reserve-top=2 -->\n{}", table("D", 5)),
+            "markers = { D = \"percentile\" }",
+            "d100",
+            "1d100",
+        ),
+        (
+            format!("{}|*|Roll twice|\n", table("d", 13)),
+            "",
+            "d20",
+            "1d20",
+        ),
+        (table("d20", 4), "range-separator = \"-\"", "d20", "1d20"),
+    ];
+
+    for (markdown, cfg, die, formula) in &cases {
+        let tables = export(markdown, cfg);
+        assert_eq!(tables.len(), 1, "{}", markdown);
+        assert_eq!(&tables[0].die, die);
+        assert_eq!(tables[0].formula.as_deref(), Some(*formula), "{}", die);
+        check(&tables[0]);
+    }
+}
+
+#[test]
+fn no_formula() {
+    let cfg = "[custom-dice]\nomen = [\"Sun\", \"Sun\", \"Moon\", \"Star\"]";
+    let tables = export(&table("d:omen", 3), cfg);
+    assert_eq!(tables[0].formula, None);
+    assert!(tables[0].rows.iter().all(|row| row.bounds.is_empty()));
+}
+
+/// Every result of a formula made of dice like `2d6`, numbers, `+`, `-`,
+/// `*` and parentheses.
+fn evaluate(formula: &str) -> BTreeSet<i64> {
+    let tokens: Vec<char> = formula.chars().collect();
+    let mut i = 0;
+    let results = sum(&tokens, &mut i);
+    assert_eq!(i, tokens.len(), "can't read {}", formula);
+    results
+}
+
+fn sum(tokens: &[char], i: &mut usize) -> BTreeSet<i64> {
+    let mut results = product(tokens, i);
+    while let Some(&op) = tokens.get(*i).filter(|c| matches!(c, '+' | '-')) {
+        *i += 1;
+        let other = product(tokens, i);
+        let sign = if op == '+' { 1 } else { -1 };
+        results = combine(&results, &other, |a, b| a + sign * b);
+    }
+    results
+}
+
+fn product(tokens: &[char], i: &mut usize) -> BTreeSet<i64> {
+    let mut results = atom(tokens, i);
+    while tokens.get(*i) == Some(&'*') {
+        *i += 1;
+        let other = atom(tokens, i);
+        results = combine(&results, &other, |a, b| a * b);
+    }
+    results
+}
+
+fn atom(tokens: &[char], i: &mut usize) -> BTreeSet<i64> {
+    if tokens.get(*i) == Some(&'(') {
+        *i += 1;
+        let results = sum(tokens, i);
+        assert_eq!(tokens.get(*i), Some(&')'));
+        *i += 1;
+        return results;
+    }
+
+    let count = number(tokens, i);
+    if tokens.get(*i) != Some(&'d') {
+        return BTreeSet::from([count]);
+    }
+    *i += 1;
+    let sides = number(tokens, i);
+    let die: BTreeSet<i64> = (1..=sides).collect();
+    (1..count).fold(die.clone(), |results, _| {
+        combine(&results, &die, |a, b| a + b)
+    })
+}
+
+fn number(tokens: &[char], i: &mut usize) -> i64 {
+    let start = *i;
+    while tokens.get(*i).is_some_and(char::is_ascii_digit) {
+        *i += 1;
+    }
+    tokens[start..*i]
+        .iter()
+        .collect::<String>()
+        .parse()
+        .unwrap()
+}
+
+fn combine(a: &BTreeSet<i64>, b: &BTreeSet<i64>, f: impl Fn(i64, i64) -> i64) -> BTreeSet<i64> {
+    a.iter()
+        .flat_map(|&x| b.iter().map(move |&y| (x, y)))
+        .map(|(x, y)| f(x, y))
+        .collect()
+}
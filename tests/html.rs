@@ -0,0 +1,128 @@
+//! Renders fixtures with mdBook's HTML renderer before and after the
+//! conversion. Everything but the die column has to render the same, with
+//! and without `curly-quotes`. Converted tables are also compared with
+//! pulldown-cmark's HTML.
+
+use mdbook::utils::render_markdown;
+use mdbook_rolltables::pulldown_cmark::{self, Options, Parser};
+use mdbook_rolltables::RollTables;
+use std::fs;
+use std::path::Path;
+
+/// Fixtures whose chapters are mostly prose, and use the markdown
+/// extensions mdBook renders with.
+const FIXTURES: &[&str] = &["markdown-extensions", "smart-punctuation"];
+
+#[test]
+fn prose() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    for name in FIXTURES {
+        let input = fs::read_to_string(root.join(name).join("input.md")).unwrap();
+        let expected = fs::read_to_string(root.join(name).join("expected.md")).unwrap();
+
+        for curly_quotes in [false, true] {
+            let (prose, tables) = split_tables(&render_markdown(&input, curly_quotes));
+            let (converted_prose, converted) =
+                split_tables(&render_markdown(&expected, curly_quotes));
+            assert_eq!(prose, converted_prose, "{}", name);
+            assert_eq!(tables.len(), converted.len(), "{}", name);
+
+            for (table, converted) in tables.iter().zip(&converted) {
+                let (rows, converted_rows) = (rows(table), rows(converted));
+                assert_eq!(rows.len(), converted_rows.len(), "{}:\n{}", name, converted);
+                for (row, converted_row) in rows.iter().zip(&converted_rows) {
+                    // Wrapped cells only differ in where the lines break.
+                    let converted_row: Vec<String> = converted_row
+                        .iter()
+                        .map(|cell| cell.replace("<br>", " "))
+                        .collect();
+                    assert_eq!(row[1..], converted_row[1..], "{}:\n{}", name, converted);
+                }
+            }
+        }
+    }
+}
+
+/// `html` without its tables, and the tables.
+fn split_tables(html: &str) -> (String, Vec<String>) {
+    let mut prose = String::new();
+    let mut tables = vec![];
+    let mut rest = html;
+    while let (Some(start), Some(end)) = (rest.find("<table"), rest.find("</table>")) {
+        prose.push_str(&rest[..start]);
+        tables.push(rest[start..end].to_string());
+        rest = &rest[end + "</table>".len()..];
+    }
+    prose.push_str(rest);
+    (prose, tables)
+}
+
+/// The cells of every row of `table`, the head first.
+fn rows(table: &str) -> Vec<Vec<String>> {
+    table.split("<tr>").skip(1).map(cells).collect()
+}
+
+/// The content of every `<th>` and `<td>` in `html`, whatever their
+/// attributes.
+fn cells(html: &str) -> Vec<String> {
+    let mut cells = vec![];
+    for (start, _) in html.match_indices('<') {
+        let tag = &html[start..];
+        let is_cell = ["<th", "<td"]
+            .iter()
+            .any(|name| tag.starts_with(name) && matches!(tag.as_bytes()[3], b' ' | b'>'));
+        if let (true, Some(open_end), Some(end)) = (is_cell, tag.find('>'), tag.find("</t")) {
+            cells.push(tag[open_end + 1..end].trim().to_string());
+        }
+    }
+    cells
+}
+
+/// A converted table renders like the same table written by hand, through
+/// the pulldown-cmark the tables are parsed with, including cells that are
+/// written again.
+#[test]
+fn table() {
+    let markdown = "<!-- rolltables: wrap-col=2:8 -->\n\
+         |d|Class|Page|\n|:---|:---:|--:|\n||**Warrior**|1|\n||Thief \\| Rogue|2|\n";
+    let converted = RollTables::default()
+        .process_markdown(markdown, None)
+        .unwrap();
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(
+        &mut html,
+        Parser::new_ext(&converted, Options::ENABLE_TABLES),
+    );
+    assert_eq!(
+        html,
+        "<!-- rolltables: wrap-col=2:8 -->\n\
+         <table><thead><tr><th align=\"left\">d2</th><th align=\"center\">Class</th>\
+         <th align=\"right\">Page</th></tr></thead><tbody>\n\
+         <tr><td align=\"left\">1</td><td align=\"center\"><strong>Warrior</strong></td>\
+         <td align=\"right\">1</td></tr>\n\
+         <tr><td align=\"left\">2</td><td align=\"center\">Thief |<br>Rogue</td>\
+         <td align=\"right\">2</td></tr>\n\
+         </tbody></table>\n"
+    );
+}
+
+/// Tables indented under list items stay in them. A table indented by 4
+/// spaces under `-` is a paragraph to mdBook, and isn't converted.
+#[test]
+fn nested_lists() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nested-lists");
+    let expected = fs::read_to_string(root.join("expected.md")).unwrap();
+    let html = render_markdown(&expected, false);
+
+    let tables: Vec<usize> = html.match_indices("<table").map(|(i, _)| i).collect();
+    assert_eq!(tables.len(), 2, "{}", html);
+    for i in tables {
+        let before = &html[..i];
+        assert!(
+            before.matches("<li>").count() > before.matches("</li>").count(),
+            "{}",
+            html
+        );
+    }
+    assert!(html.contains("<p>|d|Weather|"), "{}", html);
+}
@@ -0,0 +1,77 @@
+//! Loads a book from disk and checks the `manifest` it gets against its
+//! source files, the way a tool reading the manifest would.
+
+use mdbook::preprocess::{Preprocessor, PreprocessorContext};
+use mdbook::MDBook;
+use mdbook_rolltables::RollTables;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+const TRAVEL: &str = "# Travel\n\nRoll when the party sets out.\n\n\
+     |d|Weather|\n|---|---|\n||Rain|\n||Sun|\n||Fog|\n\n\
+     1. Then a road:\n\n   |d|Road|\n   |---|---|\n   ||North|\n   ||South|\n";
+
+const CAMP: &str = "\u{feff}# Camp\r\n\r\n|d|Night|\r\n|---|---|\r\n||Wolves|\r\n||Quiet|\r\n\
+     \r\n|Not|Rolled|\r\n|---|---|\r\n|a|b|\r\n";
+
+#[test]
+fn round_trip() {
+    let root = Path::new(env!("CARGO_TARGET_TMPDIR")).join("manifest-book");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("src/rules")).unwrap();
+    fs::write(root.join("book.toml"), "[book]\ntitle = \"Tables\"\n").unwrap();
+    fs::write(
+        root.join("src/SUMMARY.md"),
+        "# Summary\n\n- [Travel](rules/travel.md)\n- [Camp](camp.md)\n",
+    )
+    .unwrap();
+    fs::write(root.join("src/rules/travel.md"), TRAVEL).unwrap();
+    fs::write(root.join("src/camp.md"), CAMP).unwrap();
+
+    let ctx: PreprocessorContext = serde_json::from_value(json!({
+        "root": root,
+        "config": { "preprocessor": { "rolltables": { "manifest": "manifest.json" } } },
+        "renderer": "html",
+        "mdbook_version": mdbook::MDBOOK_VERSION,
+    }))
+    .unwrap();
+    let book = MDBook::load(&root).unwrap().book;
+    RollTables::default().run(&ctx, book).unwrap();
+
+    let manifest = fs::read_to_string(root.join("manifest.json")).unwrap();
+    let manifest: Value = serde_json::from_str(&manifest).unwrap();
+    let tables = manifest["tables"].as_array().unwrap();
+    let expected = [
+        (
+            "rules/travel.md",
+            "d6",
+            3,
+            "|d|Weather|\n|---|---|\n||Rain|\n||Sun|\n||Fog|",
+        ),
+        (
+            "rules/travel.md",
+            "d2",
+            2,
+            "|d|Road|\n   |---|---|\n   ||North|\n   ||South|",
+        ),
+        (
+            "camp.md",
+            "d2",
+            2,
+            "|d|Night|\r\n|---|---|\r\n||Wolves|\r\n||Quiet|",
+        ),
+    ];
+    assert_eq!(tables.len(), expected.len(), "{}", manifest);
+
+    for (table, (path, die, rows, source)) in tables.iter().zip(expected) {
+        assert_eq!(table["path"], path);
+        assert_eq!(table["die"], die);
+        assert_eq!(table["rows"], rows);
+
+        let file = fs::read_to_string(root.join("src").join(path)).unwrap();
+        let start = table["start"].as_u64().unwrap() as usize;
+        let end = table["end"].as_u64().unwrap() as usize;
+        assert_eq!(file[start..end].trim_end(), source, "{}", table);
+    }
+}
@@ -0,0 +1,51 @@
+//! Reads back the `<!-- rolltables-meta: {...} -->` comment of
+//! `emit-metadata` the way a preprocessor running after this one would.
+
+use mdbook_rolltables::RollTables;
+use serde_json::{json, Value};
+use toml::value::Table;
+
+const CHAPTER: &str = "# Caves\n\n|d|Cave|\n|---|---|\n||Bats|\n||Damp|\n||Dark|\n\n\
+     <!-- rolltables: name=\"<b>Loot</b> -- or -->\" -->\n\
+     |d|Loot|\n|---|---|\n||Gold|\n||Gems|\n";
+
+#[test]
+fn parse_back() {
+    let cfg: Table = toml::from_str("emit-metadata = true").unwrap();
+    let converted = RollTables::default()
+        .process_markdown(CHAPTER, Some(&cfg))
+        .unwrap();
+
+    let (comment, rest) = converted.split_once('\n').unwrap();
+    let json = comment
+        .strip_prefix("<!-- rolltables-meta: ")
+        .and_then(|comment| comment.strip_suffix(" -->"))
+        .unwrap();
+    // The escapes keep the name from ending the comment early.
+    assert!(!json.contains("--") && !json.contains('<') && !json.contains('>'));
+
+    let metadata: Value = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        metadata,
+        json!({
+            "tables": [
+                {
+                    "name": "stdin-caves-1",
+                    "die": "d6",
+                    "faces": [["1", "2"], ["3", "4"], ["5", "6"]],
+                },
+                {
+                    "name": "<b>Loot</b> -- or -->",
+                    "die": "d2",
+                    "faces": [["1"], ["2"]],
+                },
+            ]
+        })
+    );
+
+    // Apart from the comment the chapter is converted as without the option.
+    let without = RollTables::default()
+        .process_markdown(CHAPTER, None)
+        .unwrap();
+    assert_eq!(rest, without);
+}
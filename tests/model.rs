@@ -0,0 +1,52 @@
+//! Converts tables between [`MarkdownTable`] and [`RollTable`] in both
+//! directions, the way a tool working with the tables of a book would.
+
+use mdbook::book::{Book, Chapter};
+use mdbook_rolltables::{MarkdownTable, RollRow, RollTable, RollTables};
+use std::convert::TryFrom;
+
+#[test]
+fn round_trip() {
+    let markdown = "|d|Class|Armor|\n|---|---|---|\n||**Warrior**|Heavy|\n||Thief|Light|\n";
+    let mut book = Book::new();
+    book.push_item(Chapter::new(
+        "Classes",
+        markdown.into(),
+        "classes.md",
+        vec![],
+    ));
+
+    // The hook gets the table after it's converted.
+    let mut tables = vec![];
+    RollTables::default()
+        .process_with(book, None, |table, _| {
+            tables.push(RollTable::try_from(&*table).unwrap())
+        })
+        .unwrap();
+
+    let row = |faces: &[&str], cells: &[&str]| RollRow {
+        faces: faces.iter().map(|face| face.to_string()).collect(),
+        bounds: vec![],
+        cells: cells.iter().map(|cell| cell.to_string()).collect(),
+    };
+    let expected = RollTable {
+        name: String::new(),
+        die: "d2".into(),
+        formula: None,
+        columns: vec!["Class".into(), "Armor".into()],
+        rows: vec![
+            row(&["1"], &["**Warrior**", "Heavy"]),
+            row(&["2"], &["Thief", "Light"]),
+        ],
+    };
+    assert_eq!(tables, vec![expected.clone()]);
+
+    let table = MarkdownTable::from(&expected);
+    assert_eq!(table.head().len(), 3);
+    assert_eq!(table.rows().len(), 2);
+    assert_eq!(RollTable::try_from(&table).unwrap(), expected);
+    assert_eq!(
+        expected.to_markdown(),
+        "|d2|Class|Armor|\n|---|---|---|\n|1|**Warrior**|Heavy|\n|2|Thief|Light|"
+    );
+}
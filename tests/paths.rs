@@ -0,0 +1,57 @@
+//! Chapter paths in warnings and the manifest are written with `/` whichever
+//! separator they have, and chapters without one are named instead.
+
+use mdbook::book::{Book, Chapter};
+use mdbook::preprocess::{Preprocessor, PreprocessorContext};
+use mdbook_rolltables::RollTables;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::Table;
+
+const TABLE: &str = "|d|Result|\n|---|---|\n||1|\n||2|\n||3|\n||4|\n||5|\n||6|\n||7|\n";
+
+/// Where the warning about the d7 of `chapter` is.
+fn location(chapter: Chapter) -> String {
+    let cfg: Table = toml::from_str("strict = true\nwarn-unusual-dice = true").unwrap();
+    let mut book = Book::new();
+    book.push_item(chapter);
+    let error = RollTables::default()
+        .roll_tables(book, Some(&cfg))
+        .unwrap_err()
+        .to_string();
+    error.rsplit_once(" in ").unwrap().1.into()
+}
+
+#[test]
+fn warnings() {
+    for path in ["rules/travel.md", "rules\\travel.md"] {
+        let chapter = Chapter::new("Travel", TABLE.into(), PathBuf::from(path), vec![]);
+        assert_eq!(location(chapter), "rules/travel.md", "{}", path);
+    }
+
+    let mut draft = Chapter::new_draft("Travel", vec![]);
+    draft.content = TABLE.into();
+    assert_eq!(location(draft), "chapter \"Travel\"");
+}
+
+#[test]
+fn manifest() {
+    let root = Path::new(env!("CARGO_TARGET_TMPDIR")).join("paths-book");
+    fs::create_dir_all(&root).unwrap();
+    let ctx: PreprocessorContext = serde_json::from_value(json!({
+        "root": root,
+        "config": { "preprocessor": { "rolltables": { "manifest": "manifest.json" } } },
+        "renderer": "html",
+        "mdbook_version": mdbook::MDBOOK_VERSION,
+    }))
+    .unwrap();
+    let mut book = Book::new();
+    let path = PathBuf::from("rules\\travel.md");
+    book.push_item(Chapter::new("Travel", TABLE.into(), path, vec![]));
+    RollTables::default().run(&ctx, book).unwrap();
+
+    let manifest = fs::read_to_string(root.join("manifest.json")).unwrap();
+    let manifest: Value = serde_json::from_str(&manifest).unwrap();
+    assert_eq!(manifest["tables"][0]["path"], "rules/travel.md");
+}
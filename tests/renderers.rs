@@ -0,0 +1,82 @@
+//! Runs the `convert-in` and `debug-keep-original` fixtures through
+//! [`Preprocessor::run`] for several renderers, which the fixture tests
+//! don't have. The renderers `convert-in` lists get `expected.md`, the
+//! others the chapter as it was.
+
+use mdbook::book::{Book, BookItem, Chapter};
+use mdbook::preprocess::{Preprocessor, PreprocessorContext};
+use mdbook_rolltables::RollTables;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use toml::value::Table;
+
+#[test]
+fn convert_in() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let dir = root.join("convert-in");
+    let input = fs::read_to_string(dir.join("input.md")).unwrap();
+    let expected = fs::read_to_string(dir.join("expected.md")).unwrap();
+    let mut cfg: Table =
+        toml::from_str(&fs::read_to_string(root.join("config.toml")).unwrap()).unwrap();
+    cfg.extend(
+        toml::from_str::<Table>(&fs::read_to_string(dir.join("config.toml")).unwrap()).unwrap(),
+    );
+
+    for (renderer, output) in [("html", &expected), ("epub", &input), ("markdown", &input)] {
+        let ctx: PreprocessorContext = serde_json::from_value(json!({
+            "root": root,
+            "config": { "preprocessor": { "rolltables": cfg } },
+            "renderer": renderer,
+            "mdbook_version": mdbook::MDBOOK_VERSION,
+        }))
+        .unwrap();
+        let mut book = Book::new();
+        book.push_item(Chapter::new("Weather", input.clone(), "weather.md", vec![]));
+
+        let book = RollTables::default().run(&ctx, book).unwrap();
+        match &book.sections[0] {
+            BookItem::Chapter(chapter) => assert_eq!(&chapter.content, output, "{}", renderer),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// `debug-keep-original = true` only keeps the original for the html
+/// renderer, `"always"` for every renderer.
+#[test]
+fn debug_keep_original() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/debug-keep-original");
+    let input = fs::read_to_string(dir.join("input.md")).unwrap();
+    let expected = fs::read_to_string(dir.join("expected.md")).unwrap();
+
+    for (keep, renderer, kept) in [
+        (json!(true), "html", true),
+        (json!(true), "markdown", false),
+        (json!("always"), "markdown", true),
+        (json!(false), "html", false),
+    ] {
+        let ctx: PreprocessorContext = serde_json::from_value(json!({
+            "root": dir,
+            "config": { "preprocessor": { "rolltables": { "debug-keep-original": keep } } },
+            "renderer": renderer,
+            "mdbook_version": mdbook::MDBOOK_VERSION,
+        }))
+        .unwrap();
+        let mut book = Book::new();
+        book.push_item(Chapter::new("Weather", input.clone(), "weather.md", vec![]));
+
+        let book = RollTables::default().run(&ctx, book).unwrap();
+        let content = match &book.sections[0] {
+            BookItem::Chapter(chapter) => &chapter.content,
+            _ => unreachable!(),
+        };
+        let message = format!("{} {}", keep, renderer);
+        if kept {
+            assert_eq!(content, &expected, "{}", message);
+        } else {
+            assert!(!content.contains("rolltables-original"), "{}", message);
+            assert!(expected.starts_with(content.as_str()), "{}", message);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Converts a book with `screen-chapter` and compares the chapter it adds
+//! with `tests/snapshots/screen.md`. With `UPDATE_FIXTURES=1` the snapshot
+//! is rewritten instead.
+
+mod common;
+
+use common::diff;
+use mdbook::book::{Book, BookItem, Chapter};
+use mdbook_rolltables::RollTables;
+use std::{env, fs, path::Path};
+use toml::value::Table;
+
+const TRAVEL: &str = "# Travel\n\n<!-- rolltables: screen -->\n\
+     |d|Weather|\n|---|---|\n||Rain|\n||Sun|\n||Fog|\n\n\
+     |d|Road|\n|---|---|\n||North|\n||South|\n";
+
+const CAMP: &str = "# Camp\n\n<!-- rolltables: chapter screen -->\n\
+     <!-- rolltables: name=\"Night watch\" -->\n\
+     |d|Night|\n|---|---|\n||Wolves|\n||Quiet|\n||Rain|\n||Bandits|\n";
+
+#[test]
+fn screen_chapter() {
+    let cfg: Table = toml::from_str("screen-chapter = \"GM Screen\"").unwrap();
+    let mut book = Book::new();
+    book.push_item(Chapter::new("Travel", TRAVEL.into(), "travel.md", vec![]));
+    book.push_item(Chapter::new("Camp", CAMP.into(), "camp.md", vec![]));
+    let book = RollTables::default()
+        .process_with(book, Some(&cfg), |_, _| {})
+        .unwrap();
+
+    let chapters: Vec<&Chapter> = book
+        .sections
+        .iter()
+        .filter_map(|item| match item {
+            BookItem::Chapter(chapter) => Some(chapter),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(chapters.len(), 3);
+    assert_eq!(chapters[2].name, "GM Screen");
+    let actual = &chapters[2].content;
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/screen.md");
+    if env::var("UPDATE_FIXTURES").is_ok_and(|v| v == "1") {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        *actual == expected,
+        "the screen chapter differs from {}, UPDATE_FIXTURES=1 rewrites it:\n\n{}",
+        path.display(),
+        diff(&expected, actual)
+    );
+}
@@ -0,0 +1,228 @@
+//! Writes the die [`select_die`] picks for every number of rows from 1 to
+//! 120 as a markdown table, one column per set of options, and compares it
+//! with `tests/snapshots/select_die.md`, so a change to the selection shows
+//! up as a diff of that table. With `UPDATE_FIXTURES=1` the snapshot is
+//! rewritten instead.
+
+mod common;
+
+use common::diff;
+use mdbook_rolltables::{select_die, DieChoice, MarkerPolicy, RollTables};
+use std::sync::{Arc, Mutex};
+use std::{env, fs, path::Path};
+use toml::value::Table;
+
+#[test]
+fn select_die_snapshot() {
+    let defaults = RollTables::default().config(None).unwrap();
+    let default_die = RollTables::builder()
+        .default_die(20)
+        .build()
+        .config(None)
+        .unwrap();
+    let double = RollTables::builder()
+        .double_small_tables(vec![2, 3, 5])
+        .build()
+        .config(None)
+        .unwrap();
+    let columns = [
+        ("default", defaults.dice_options(MarkerPolicy::Auto)),
+        (
+            "percentile",
+            defaults.dice_options(MarkerPolicy::Percentile),
+        ),
+        (
+            "combination",
+            defaults.dice_options(MarkerPolicy::Combination),
+        ),
+        (
+            "default-die = 20",
+            default_die.dice_options(MarkerPolicy::Auto),
+        ),
+        (
+            "double-small-tables = [2, 3, 5]",
+            double.dice_options(MarkerPolicy::Auto),
+        ),
+    ];
+
+    let mut actual = String::from("|rows");
+    for (name, _) in &columns {
+        actual.push_str(&format!("|{}", name));
+    }
+    actual.push_str("|\n|---:");
+    actual.push_str(&"|---".repeat(columns.len()));
+    actual.push_str("|\n");
+    for count in 1..=120 {
+        actual.push_str(&format!("|{}", count));
+        for (_, options) in &columns {
+            let die = match select_die(count, options) {
+                DieChoice::Die(die) => format!("d{}", die),
+                DieChoice::Pair(a, b) => format!("d{}d{}", a, b),
+                choice => unreachable!("{:?}", choice),
+            };
+            actual.push_str(&format!("|{}", die));
+        }
+        actual.push_str("|\n");
+    }
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/select_die.md");
+    if env::var("UPDATE_FIXTURES").is_ok_and(|v| v == "1") {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        actual == expected,
+        "the dice picked differ from {}, UPDATE_FIXTURES=1 rewrites it:\n\n{}",
+        path.display(),
+        diff(&expected, &actual)
+    );
+}
+
+/// Every number of rows that several pairs of standard dice multiply to gets
+/// the pair with the smaller largest die.
+#[test]
+fn combination_ties() {
+    let options = RollTables::default()
+        .config(None)
+        .unwrap()
+        .dice_options(MarkerPolicy::Combination);
+    for (count, pair) in [(48, (8, 6)), (80, (10, 8)), (120, (12, 10))] {
+        assert_eq!(
+            select_die(count, &options),
+            DieChoice::Pair(pair.0, pair.1),
+            "{}",
+            count
+        );
+    }
+}
+
+/// With `verbose` the pairs that lost the tie-break are reported, and
+/// nothing is for a table that only one pair fits.
+#[test]
+fn verbose_ties() {
+    let table = |rows: usize| {
+        let mut markdown = String::from("|dd|Result|\n|---|---|\n");
+        for row in 0..rows {
+            markdown.push_str(&format!("||{}|\n", row));
+        }
+        markdown
+    };
+    let markdown = format!("{}\n{}", table(48), table(36));
+    let cfg: Table = toml::from_str("verbose = true\n[markers]\ndd = \"combination\"").unwrap();
+    let report = Arc::new(Mutex::new(String::new()));
+    let sink = Arc::clone(&report);
+    RollTables::builder()
+        .report_to(move |text| sink.lock().unwrap().push_str(text))
+        .build()
+        .process_markdown(&markdown, Some(&cfg))
+        .unwrap();
+
+    assert_eq!(
+        *report.lock().unwrap(),
+        "rolltables: 1 note\n  \
+         - Picked d86 for 48 rows rather than d124, its largest die is smaller in <stdin>\n"
+    );
+}
+
+#[test]
+fn describe() {
+    let cases = [
+        (
+            DieChoice::Die(6),
+            "d6",
+            vec![1; 6],
+            "auto-selected d6 (6 rows)",
+        ),
+        (
+            DieChoice::Die(20),
+            "d20",
+            vec![2; 10],
+            "auto-selected d20 (10 rows, 2 faces each)",
+        ),
+        (
+            DieChoice::Pair(6, 6),
+            "d66",
+            vec![1; 36],
+            "auto-selected d66 (36 rows)",
+        ),
+        (
+            DieChoice::Pair(4, 6),
+            "d4.6",
+            vec![1; 24],
+            "auto-selected d4.6 (24 rows)",
+        ),
+        (
+            DieChoice::Explicit,
+            "d100",
+            vec![34, 33, 33],
+            "explicit d100 (3 rows, 33 to 34 faces)",
+        ),
+        (
+            DieChoice::Die(6),
+            "d6",
+            vec![3, 1, 2],
+            "auto-selected d6 (3 rows, 1 to 3 faces)",
+        ),
+        (
+            DieChoice::Bands,
+            "2d6",
+            vec![3, 12, 15, 6],
+            "banded 2d6 (4 rows)",
+        ),
+        (
+            DieChoice::Custom,
+            "dF",
+            vec![2, 1],
+            "custom dF (2 rows, 1 to 2 faces)",
+        ),
+        (
+            DieChoice::Chances,
+            "d100",
+            vec![15, 15, 70],
+            "percentage d100 (3 rows, 15 to 70 faces)",
+        ),
+        (
+            DieChoice::Matrix,
+            "d4xd6",
+            vec![6; 4],
+            "auto-selected d4xd6 (4 rows, 6 columns)",
+        ),
+    ];
+    for (choice, die, weights, expected) in cases {
+        assert_eq!(choice.describe(die, &weights), expected);
+    }
+}
+
+/// Every table in the stats says how its die was picked, with the header it
+/// got.
+#[test]
+fn stats() {
+    let markdown = "|d|Weather|\n|---|---|\n||Rain|\n||Sun|\n||Fog|\n\n\
+         |d100|Omen|\n|---|---|\n||Crows|\n||Comet|\n||Nothing|\n\n\
+         |d|Chance|\n|---|---|\n|15%|Rain|\n||Sun|\n";
+    let report = Arc::new(Mutex::new(String::new()));
+    let sink = Arc::clone(&report);
+    let cfg: Table = toml::from_str("stats = true").unwrap();
+    RollTables::builder()
+        .report_to(move |text| sink.lock().unwrap().push_str(text))
+        .build()
+        .process_markdown(markdown, Some(&cfg))
+        .unwrap();
+
+    let report = report.lock().unwrap();
+    let lines: Vec<&str> = report
+        .lines()
+        .filter(|line| line.starts_with("  stdin"))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "  stdin-table-1 in <stdin>: auto-selected d6 (3 rows, 2 faces each)",
+            "  stdin-table-2 in <stdin>: explicit d100 (3 rows, 33 to 34 faces)",
+            "  stdin-table-3 in <stdin>: percentage d100 (2 rows, 15 to 85 faces)",
+        ],
+        "{}",
+        report
+    );
+}
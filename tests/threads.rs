@@ -0,0 +1,67 @@
+//! Converts books at the same time on several threads, each with its own
+//! [`RollTablesBuilder::report_to`].
+
+use mdbook_rolltables::{Config, MarkdownTable, RollTable, RollTables, RollTablesBuilder};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use toml::value::Table;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn send_sync() {
+    assert_send_sync::<RollTables>();
+    assert_send_sync::<RollTablesBuilder>();
+    assert_send_sync::<Config>();
+    assert_send_sync::<RollTable>();
+    assert_send_sync::<MarkdownTable>();
+}
+
+/// A table with `rows` rows and `marker` in the header.
+fn table(marker: &str, rows: usize) -> String {
+    let mut markdown = format!("|{}|Result|\n|---|---|\n", marker);
+    for row in 0..rows {
+        markdown.push_str(&format!("||{}|\n", row));
+    }
+    markdown
+}
+
+#[test]
+fn two_books() {
+    let cfg: Table = toml::from_str("warn-unusual-dice = true\nstats = true").unwrap();
+    let books = [table("d", 7), table("d", 9)];
+
+    let threads: Vec<_> = books
+        .iter()
+        .map(|book| {
+            let (book, cfg) = (book.clone(), cfg.clone());
+            let report = Arc::new(Mutex::new(String::new()));
+            let sink = Arc::clone(&report);
+            let preprocessor = RollTables::builder()
+                .report_to(move |text| sink.lock().unwrap().push_str(text))
+                .build();
+            let thread = thread::spawn(move || {
+                let mut converted = String::new();
+                for _ in 0..20 {
+                    converted = preprocessor.process_markdown(&book, Some(&cfg)).unwrap();
+                }
+                converted
+            });
+            (thread, report)
+        })
+        .collect();
+
+    let dice = [("d7", "d9"), ("d9", "d7")];
+    for ((thread, report), (die, other)) in threads.into_iter().zip(dice) {
+        let converted = thread.join().unwrap();
+        assert!(converted.contains(die), "{}", converted);
+        let report = report.lock().unwrap();
+        assert_eq!(report.matches("rolltables: 1 warning\n").count(), 20);
+        assert_eq!(report.matches("rolltables: 1 roll tables").count(), 20);
+        assert!(
+            report.contains(die) && !report.contains(other),
+            "{}",
+            report
+        );
+    }
+}
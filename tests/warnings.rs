@@ -0,0 +1,320 @@
+//! Pins the warnings of tables whose die is only chosen once reserved, overflow
+//! and weighted rows are accounted for, of a default die that doesn't fit, of
+//! unbalanced protected regions and of tables hidden in HTML, what a
+//! `DiagnosticSink` gets, the errors of chances that don't add up and the
+//! wording for a single row.
+
+use mdbook_rolltables::{Diagnostic, DiagnosticSink, RollTables, UnusualDice, WarningKind};
+use std::sync::{Arc, Mutex};
+use toml::value::Table;
+
+/// The warnings of converting `markdown` with `cfg`, one per line, or an
+/// empty string if there are none.
+fn warnings(markdown: &str, cfg: &str) -> String {
+    let mut cfg: Table = toml::from_str(cfg).unwrap();
+    cfg.insert("strict".into(), true.into());
+    cfg.entry("warn-unusual-dice").or_insert(true.into());
+    match RollTables::default().process_markdown(markdown, Some(&cfg)) {
+        Ok(_) => String::new(),
+        Err(error) => error
+            .to_string()
+            .strip_prefix("Warnings are errors because strict is set:\n")
+            .unwrap()
+            .into(),
+    }
+}
+
+/// A table with `rows` rows and `marker` in the header.
+fn table(marker: &str, rows: usize) -> String {
+    let mut markdown = format!("|{}|Result|\n|---|---|\n", marker);
+    for row in 0..rows {
+        markdown.push_str(&format!("||{}|\n", row));
+    }
+    markdown
+}
+
+/// Each table is warned about for the die it is converted with, e.g. not a
+/// d5 for 5 rows below two reserved ones.
+#[test]
+fn final_die() {
+    let cases = [
+        (
+            table("d", 19),
+            "",
+            "d19; remove 7 rows for d12, or add 1 for d20",
+        ),
+        (
+            format!("<!-- rolltables: reserve-top=2 -->\n{}", table("d", 5)),
+            "",
+            "",
+        ),
+        (
+            format!("<!-- rolltables: reserve-top=2 -->\n{}", table("d", 6)),
+            "",
+            "",
+        ),
+        (
+            format!("<!-- rolltables: reserve-top=2 -->\n{}", table("d20", 19)),
+            "",
+            "",
+        ),
+        (format!("{}|*|Roll twice|\n", table("d", 18)), "", ""),
+        (
+            format!("{}|*|Roll twice|\n", table("d", 9)),
+            "default-die = 10",
+            "",
+        ),
+        (
+            "```rolltable\n2: a\nb\n2: c\n```\n".into(),
+            "",
+            "d5; remove 1 face for d4, or add 1 for d6",
+        ),
+        (table("d", 7), "warn-unusual-dice = false", ""),
+        ("```rolltable\n2: a\n2: b\n```\n".into(), "", ""),
+        (
+            format!("{}||0|\n", table("d", 6)),
+            "merge-duplicates = \"all\"",
+            "d7; remove 1 row for d6, or add 1 for d8",
+        ),
+    ];
+
+    for (markdown, cfg, die) in &cases {
+        let expected = if die.is_empty() {
+            String::new()
+        } else {
+            format!("Roll table created with unusual dice: {} in <stdin>", die)
+        };
+        assert_eq!(warnings(markdown, cfg), expected, "{}", markdown);
+    }
+}
+
+/// The suggestion for an unusual die names the nearest standard die below
+/// and above it. The dice are written in the header, as auto-selecting 16
+/// rows gives a d44.
+#[test]
+fn suggestions() {
+    let cases = [
+        (5, "remove 1 row for d4, or add 1 for d6"),
+        (7, "remove 1 row for d6, or add 1 for d8"),
+        (9, "remove 1 row for d8, or add 1 for d10"),
+        (11, "remove 1 row for d10, or add 1 for d12"),
+        (13, "remove 1 row for d12, or add 7 for d20"),
+        (14, "remove 2 rows for d12, or add 6 for d20"),
+        (15, "remove 3 rows for d12, or add 5 for d20"),
+        (16, "remove 4 rows for d12, or add 4 for d20"),
+        (17, "remove 5 rows for d12, or add 3 for d20"),
+        (18, "remove 6 rows for d12, or add 2 for d20"),
+        (19, "remove 7 rows for d12, or add 1 for d20"),
+        (21, "remove 1 row for d20, or add 79 for d100"),
+        (22, "remove 2 rows for d20, or add 78 for d100"),
+        (23, "remove 3 rows for d20, or add 77 for d100"),
+    ];
+
+    for (rows, suggestion) in cases {
+        assert_eq!(
+            warnings(
+                &table(&format!("d{}", rows), rows),
+                "warn-unusual-dice = \"always\""
+            ),
+            format!(
+                "Roll table created with unusual dice: d{}; {} in <stdin>",
+                rows, suggestion
+            ),
+        );
+    }
+}
+
+#[test]
+fn uneven_default_die() {
+    assert_eq!(
+        warnings(
+            &table("d", 7),
+            "default-die = 20\nwarn-unusual-dice = false"
+        ),
+        "The faces of the default-die d20 can't be split evenly between 7 rows, using the \
+         auto-selected d7 (7 rows) instead in <stdin>"
+    );
+}
+
+/// A table whose generated labels can't be used is written as it was, here
+/// a custom die with an empty face.
+#[test]
+fn failing_labels() {
+    let markdown = "|d:omen|Sign|\n|---|---|\n||Sun|\n||Nothing|\n";
+    let cfg = "custom-dice = { omen = [\"Sun\", \"\"] }";
+    assert_eq!(
+        warnings(markdown, cfg),
+        "Roll table left unconverted: generated an empty label in <stdin>"
+    );
+
+    let mut cfg: Table = toml::from_str(cfg).unwrap();
+    cfg.insert("quiet".into(), true.into());
+    let converted = RollTables::default()
+        .process_markdown(markdown, Some(&cfg))
+        .unwrap();
+    assert_eq!(converted, markdown);
+}
+
+/// Chances that leave less than 1% for every empty row say how much they
+/// leave.
+#[test]
+fn chances() {
+    let chances = |cells: &[&str]| {
+        let mut markdown = String::from("|d|Treasure|\n|---|---|\n");
+        for cell in cells {
+            markdown.push_str(&format!("|{}|x|\n", cell));
+        }
+        RollTables::default()
+            .process_markdown(&markdown, None)
+            .unwrap_err()
+            .to_string()
+    };
+    assert_eq!(
+        chances(&["50%", "40%"]),
+        "The chances of the table in <stdin> add up to 90%, not 100%"
+    );
+    assert_eq!(
+        chances(&["50%", "50%", "", ""]),
+        "The chances of the table in <stdin> already add up to 100%, leaving nothing for 2 \
+         empty rows"
+    );
+    assert_eq!(
+        chances(&["60%", "50%", ""]),
+        "The chances of the table in <stdin> already add up to 110%, leaving nothing for 1 \
+         empty row"
+    );
+    assert_eq!(
+        chances(&["98%", "", "", ""]),
+        "The chances of the table in <stdin> add up to 98%, leaving 2% for 3 empty rows"
+    );
+}
+
+/// A header like `d66` is two dice, so it needs a row for each pair of faces.
+#[test]
+fn pair_rows() {
+    assert_eq!(warnings(&table("d66", 36), ""), "");
+    assert_eq!(
+        warnings(&table("d66", 20), ""),
+        "Roll table left unconverted: d66 needs one row per pair of faces but the table has \
+         20 rows in <stdin>"
+    );
+}
+
+/// An end comment without a start is ignored, and a start without an end
+/// protects the rest of the chapter.
+#[test]
+fn unbalanced_protected_regions() {
+    let cfg = "protected-regions = [[\"<!-- tabs:start -->\", \"<!-- tabs:end -->\"]]";
+    let cases = [
+        (
+            format!("<!-- tabs:end -->\n\n{}", table("d", 4)),
+            "<!-- tabs:end --> doesn't close a protected region, ignoring it in <stdin>",
+        ),
+        (
+            format!("<!-- tabs:start -->\n\n{}", table("d", 4)),
+            "<!-- tabs:start --> isn't closed, so the tables in the rest of the chapter are \
+             left as they are in <stdin>",
+        ),
+        (
+            format!(
+                "<!-- tabs:start -->\n\n{}\n<!-- tabs:end -->\n",
+                table("d", 4)
+            ),
+            "",
+        ),
+    ];
+
+    for (markdown, expected) in &cases {
+        assert_eq!(&warnings(markdown, cfg), expected, "{}", markdown);
+    }
+}
+
+/// A roll table another preprocessor wrapped in HTML is reported if the
+/// chapter has no markdown tables left, but not one shown in a `<pre>`.
+#[test]
+fn table_in_html() {
+    let wrapped = format!("<div class=\"admonition\">\n{}</div>\n", table("d", 4));
+    let cases = [
+        (
+            wrapped.clone(),
+            "Roll table left unconverted: it's inside HTML, so it isn't read as a table. \
+             If another preprocessor put it there, `before = [\"<name>\"]` in \
+             [preprocessor.rolltables] runs this one first in <stdin>",
+        ),
+        (format!("{}\n{}", wrapped, table("d", 4)), ""),
+        (format!("<pre>\n{}</pre>\n", table("d", 4)), ""),
+    ];
+
+    for (markdown, expected) in &cases {
+        assert_eq!(&warnings(markdown, ""), expected, "{}", markdown);
+    }
+}
+
+struct Collect(Arc<Mutex<Vec<Diagnostic>>>);
+
+impl DiagnosticSink for Collect {
+    fn report(&self, diagnostic: Diagnostic) {
+        self.0.lock().unwrap().push(diagnostic);
+    }
+}
+
+#[test]
+fn sink() {
+    let diagnostics = Arc::new(Mutex::new(vec![]));
+    let preprocessor = RollTables::builder()
+        .warn_unusual_dice(UnusualDice::Picked)
+        .with_sink(Collect(Arc::clone(&diagnostics)))
+        .build();
+    let markdown = format!(
+        "|a|b|\n|---|---|\n|1|2|\n\n{}\n{}",
+        table("d", 6),
+        table("d", 7)
+    );
+    preprocessor.process_markdown(&markdown, None).unwrap();
+
+    let diagnostics = diagnostics.lock().unwrap();
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    let diagnostic = &diagnostics[0];
+    assert_eq!(diagnostic.kind, WarningKind::UnusualDie);
+    assert_eq!(diagnostic.chapter.as_deref(), Some("stdin"));
+    assert_eq!(diagnostic.table, Some(2));
+    assert_eq!(
+        diagnostic.message,
+        "Roll table created with unusual dice: d7; remove 1 row for d6, or add 1 for d8"
+    );
+    assert_eq!(
+        diagnostic.suggestion.as_deref(),
+        Some("remove 1 row for d6, or add 1 for d8")
+    );
+}
+
+/// The messages that give the number of rows say "1 row" for one.
+#[test]
+fn one_row() {
+    assert_eq!(
+        warnings(&table("d1", 1), ""),
+        "Roll table left unconverted: d1 isn't a die, did you mean d4? The table has 1 row in \
+         <stdin>"
+    );
+    assert_eq!(
+        warnings(&table("d66", 1), ""),
+        "Roll table left unconverted: d66 needs one row per pair of faces but the table has \
+         1 row in <stdin>"
+    );
+    assert_eq!(
+        warnings(
+            "|d:coin|Side|\n|---|---|\n||Heads|\n",
+            "custom-dice = { coin = [\"Heads\", \"Tails\"] }"
+        ),
+        "Roll table left unconverted: custom die \"coin\" has 2 different faces but the table \
+         has 1 row in <stdin>"
+    );
+    assert_eq!(
+        warnings(
+            "|d:blank|Side|\n|---|---|\n||Blank|\n||Blank|\n",
+            "custom-dice = { blank = [\"Blank\"] }"
+        ),
+        "Roll table left unconverted: custom die \"blank\" has 1 different face but the table \
+         has 2 rows in <stdin>"
+    );
+}